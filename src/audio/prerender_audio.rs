@@ -4,13 +4,16 @@ use xsynth_core::channel::{ChannelAudioEvent, ChannelConfigEvent, ChannelEvent,
 use xsynth_core::channel_group::{ChannelGroup, ChannelGroupConfig, ParallelismOptions, SynthEvent, SynthFormat};
 use xsynth_core::soundfont::{EnvelopeCurveType, EnvelopeOptions, Interpolator, SampleSoundfont, SoundfontBase, SoundfontInitOptions};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex, atomic::AtomicUsize};
+use std::sync::{Arc, Mutex, MutexGuard, atomic::AtomicUsize};
 use xsynth_core::{channel_group, AudioPipe, AudioStreamParams, ChannelCount};
 use std::cell::UnsafeCell;
 
-use crate::midi::midi_track_parser::{MIDIEvent, MIDIEventType};
+use crate::midi::midi_track_parser::{MIDIEvent, MIDIEventType, SysExKind};
 use crate::util::global_timer::GlobalTimer;
 
+// how many events the generator copies out at a time, so the list isn't locked while it renders
+const EVENT_BATCH: usize = 4096;
+
 pub struct UnsafeVec<T> {
     data: UnsafeCell<Vec<T>>
 }
@@ -123,6 +126,8 @@ pub struct PrerenderAudio {
     pub cfg: StreamConfig,
 
     midi_evs: Arc<Mutex<Vec<MIDIEvent>>>,
+    // false while a streamed midi is still loading and more events can show up
+    midi_evs_complete: Arc<AtomicBool>,
     g_time: Arc<Mutex<GlobalTimer>>,
 
     pub reset_requested: Arc<AtomicBool>,
@@ -130,10 +135,15 @@ pub struct PrerenderAudio {
 
     stream_params: AudioStreamParams,
     xsynth_pre: Arc<Mutex<ChannelGroup>>,
+    // kept so the synth can be made again with a different number of ports
+    synth_config: ChannelGroupConfig,
+    soundfonts: Vec<Arc<dyn SoundfontBase>>,
+    layer_count: Option<usize>,
+    port_count: u8,
 
     pub limiter: Arc<Mutex<Limiter>>,
     generator_thread: Option<std::thread::JoinHandle<()>>,
-    start_time: f32,
+    start_time: f64,
 
     // audio settings
     pub audio_fps: f32,
@@ -153,6 +163,26 @@ impl PrerenderAudio {
 
         let stream_params = AudioStreamParams::new(cfg.sample_rate.0, ChannelCount::Stereo);
 
+        let synth_config = ChannelGroupConfig {
+            channel_init_options: ChannelInitOptions {
+                fade_out_killing: false
+            },
+            format: SynthFormat::Midi,
+            audio_params: stream_params,
+            parallelism: ParallelismOptions {
+                channel: match channel_threads {
+                    0 => channel_group::ThreadCount::Auto,
+                    1 => channel_group::ThreadCount::None,
+                    _ => channel_group::ThreadCount::Manual(channel_threads)
+                },
+                key: match key_threads {
+                    0 => channel_group::ThreadCount::Auto,
+                    1 => channel_group::ThreadCount::None,
+                    _ => channel_group::ThreadCount::Manual(key_threads)
+                }
+            }
+        };
+
         let s = Self {
             read_pos: Arc::new(AtomicUsize::new(0)),
             write_pos: Arc::new(AtomicUsize::new(0)),
@@ -160,6 +190,7 @@ impl PrerenderAudio {
             device,
             cfg,
             midi_evs: Arc::new(Mutex::new(Vec::new())),
+            midi_evs_complete: Arc::new(AtomicBool::new(true)),
 
             g_time: global_time,
 
@@ -167,31 +198,15 @@ impl PrerenderAudio {
             sample_rate: sr as f32,
 
             stream_params,
-            xsynth_pre: Arc::new(Mutex::new(ChannelGroup::new(
-                ChannelGroupConfig {
-                    channel_init_options: ChannelInitOptions {
-                        fade_out_killing: false
-                    },
-                    format: SynthFormat::Midi,
-                    audio_params: stream_params,
-                    parallelism: ParallelismOptions {
-                        channel: match channel_threads {
-                            0 => channel_group::ThreadCount::Auto,
-                            1 => channel_group::ThreadCount::None,
-                            _ => channel_group::ThreadCount::Manual(channel_threads)
-                        },
-                        key: match key_threads {
-                            0 => channel_group::ThreadCount::Auto,
-                            1 => channel_group::ThreadCount::None,
-                            _ => channel_group::ThreadCount::Manual(key_threads)
-                        }
-                    }
-                },
-            ))),
+            xsynth_pre: Arc::new(Mutex::new(ChannelGroup::new(synth_config.clone()))),
+            synth_config,
+            soundfonts: Vec::new(),
+            layer_count: None,
+            port_count: 1,
 
             limiter: Arc::new(Mutex::new(Limiter::new(0.01, 1.0, sr as f32))),
             generator_thread: None,
-            start_time: 0.0f32,
+            start_time: 0.0f64,
 
             audio_fps: 0.0f32,
             transpose: 0
@@ -200,18 +215,18 @@ impl PrerenderAudio {
         s
     }
 
-    pub fn get_buffer_seconds(&self) -> f32 {
+    pub fn get_buffer_seconds(&self) -> f64 {
         let secs = {
             let read_pos = self.read_pos.load(Ordering::Acquire);
             let write_pos = self.write_pos.load(Ordering::Acquire);
-            0.0f32.max((write_pos) as f32 - (read_pos) as f32) / self.sample_rate
+            0.0f64.max((write_pos) as f64 - (read_pos) as f64) / self.sample_rate as f64
         };
         return secs;
     }
 
-    pub fn get_player_time(&self) -> f32 {
+    pub fn get_player_time(&self) -> f64 {
         let read_pos = self.read_pos.load(Ordering::Acquire);
-        return self.start_time + (read_pos) as f32 / self.sample_rate;
+        return self.start_time + (read_pos) as f64 / self.sample_rate as f64;
     }
 
     pub fn xsynth_load_sfs(&mut self, sfs: &[String]) {
@@ -244,11 +259,13 @@ impl PrerenderAudio {
                 )
             )
         );
+        self.soundfonts = synth_soundfonts;
 
         println!("soundfonts loaded!!");
     }
 
     pub fn xsynth_set_layer_count(&mut self, layer_count: usize) {
+        self.layer_count = Some(layer_count);
         (*self.xsynth_pre.lock().unwrap()).send_event(
             SynthEvent::AllChannels(
                 ChannelEvent::Config(
@@ -261,24 +278,75 @@ impl PrerenderAudio {
         );
     }
 
-    pub fn set_midi_events(&mut self, evs: Vec<MIDIEvent>) {
+    // every port gets its own 16 channels with drums on the 10th, like a synth per port.
+    // the generator has the synth locked while it runs, so it has to be stopped first
+    pub fn set_port_count(&mut self, port_count: u8) {
+        if port_count == self.port_count {
+            return;
+        }
+        self.port_count = port_count;
+
+        let mut config = self.synth_config.clone();
+        if port_count > 1 {
+            config.format = SynthFormat::Custom { channels: port_count as u32 * 16 };
+        }
+        let mut synth = ChannelGroup::new(config);
+        if port_count > 1 {
+            for port in 0..port_count as u32 {
+                synth.send_event(SynthEvent::Channel(port * 16 + 9,
+                    ChannelEvent::Config(ChannelConfigEvent::SetPercussionMode(true))
+                ));
+            }
+        }
+        synth.send_event(SynthEvent::AllChannels(
+            ChannelEvent::Config(ChannelConfigEvent::SetSoundfonts(self.soundfonts.clone()))
+        ));
+        if self.layer_count.is_some() {
+            synth.send_event(SynthEvent::AllChannels(
+                ChannelEvent::Config(ChannelConfigEvent::SetLayerCount(self.layer_count))
+            ));
+        }
+        *self.xsynth_pre.lock().unwrap() = synth;
+    }
+
+    pub fn port_count(&self) -> u8 {
+        self.port_count
+    }
+
+    pub fn set_midi_events(&mut self, evs: Vec<MIDIEvent>, complete: bool) {
         *self.midi_evs.lock().unwrap() = evs;
+        self.midi_evs_complete.store(complete, Ordering::Release);
     }
 
+    // the events the synth plays, for exporting
+    pub fn midi_events(&self) -> MutexGuard<'_, Vec<MIDIEvent>> {
+        self.midi_evs.lock().unwrap()
+    }
 
+    // for streamed midis, evs have to come after everything that's already there
+    pub fn append_midi_events(&mut self, mut evs: Vec<MIDIEvent>, complete: bool) {
+        self.midi_evs.lock().unwrap().append(&mut evs);
+        self.midi_evs_complete.store(complete, Ordering::Release);
+    }
 
-    pub fn render_audio(&mut self, start_time: f32, speed: f32) -> std::thread::JoinHandle<()> {
+
+
+    pub fn render_audio(&mut self, start_time: f64, speed: f32) -> std::thread::JoinHandle<()> {
         // wtf.
         let write_pos = self.write_pos.clone();
         let read_pos = self.read_pos.clone();
         let midi_evs = self.midi_evs.clone();
+        let midi_evs_complete = self.midi_evs_complete.clone();
         let audio_buffer = self.audio_buffer.clone();
         let reset_requested = self.reset_requested.clone();
         let xsynth_pre = self.xsynth_pre.clone();
         let transpose = self.transpose;
         let audio_fps = self.audio_fps;
+        let port_count = self.port_count;
 
-        let sample_rate = self.sample_rate;
+        let sample_rate = self.sample_rate as f64;
+        let speed = speed as f64;
+        let audio_fps = audio_fps as f64;
         
         reset_requested.store(false, Ordering::Release);
 
@@ -323,142 +391,185 @@ impl PrerenderAudio {
                 //let needs_reset = reset_requested.lock().unwrap();
                 //let mut write = 0;
 
-                for e in &(midi_evs.lock().unwrap())[..] {
-                    if match e.command {
-                        MIDIEventType::NoteOn | MIDIEventType::NoteOff => true,
-                        _ => false
-                    } && (e.time / speed < start_time
-                        //|| e.data[2] < 15
-                        //|| e.data[2] < get_skipping_velocity(write_pos.load(Ordering::Relaxed), read_pos.load(Ordering::Relaxed))
-                    ) {
-                        continue;
+                let mut batch: Vec<MIDIEvent> = Vec::with_capacity(EVENT_BATCH);
+                let mut next_ev = 0;
+                'events: loop {
+                    batch.clear();
+                    {
+                        let evs = midi_evs.lock().unwrap();
+                        let start = next_ev.min(evs.len());
+                        batch.extend_from_slice(&evs[start..(start + EVENT_BATCH).min(evs.len())]);
                     }
+                    next_ev += batch.len();
 
-                    if write_pos.load(Ordering::Relaxed) < read_pos.load(Ordering::Relaxed) {
-                        write_pos.store(read_pos.load(Ordering::Relaxed), Ordering::Relaxed);
+                    if batch.is_empty() {
+                        // caught up with a midi that's still loading, wait for more instead of
+                        // rendering past where its next events will be
+                        if midi_evs_complete.load(Ordering::Acquire) || reset_requested.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        std::thread::sleep(std::time::Duration::from_millis(2));
+                        continue;
                     }
 
-                    let ev_time = e.time / speed;
+                    for e in &batch {
+                        // a streamed midi can bring in new ports, they play once the synth is remade for them
+                        if e.port >= port_count {
+                            continue;
+                        }
+                        let kind = e.kind();
+                        if match kind {
+                            MIDIEventType::NoteOn | MIDIEventType::NoteOff => true,
+                            _ => false
+                        } && (e.time / speed < start_time
+                            //|| e.data[1] < 15
+                            //|| e.data[1] < get_skipping_velocity(write_pos.load(Ordering::Relaxed), read_pos.load(Ordering::Relaxed))
+                        ) {
+                            continue;
+                        }
+
+                        if write_pos.load(Ordering::Relaxed) < read_pos.load(Ordering::Relaxed) {
+                            write_pos.store(read_pos.load(Ordering::Relaxed), Ordering::Relaxed);
+                        }
+
+                        let ev_time = e.time / speed;
 
-                    let offset = if audio_fps > 0.0 {
-                        f32::floor(ev_time * audio_fps) / audio_fps - start_time
-                    } else {
-                        ev_time - start_time
-                    };
-
-                    let samples = (offset * sample_rate) as isize - write_pos.load(Ordering::Relaxed) as isize;
-                    if samples > 0 {
-                        let mut samples = samples as usize;
-                        while write_pos.load(Ordering::Relaxed) + samples > read_pos.load(Ordering::Relaxed) + buff_len / 2 {
-                            let mut spare = (read_pos.load(Ordering::Relaxed) + buff_len / 2) - write_pos.load(Ordering::Relaxed);
-                            if spare > 0 {
-                                if spare > samples { spare = samples; }
-                                if spare != 0 {
-                                    /*let start = (write_pos.load(Ordering::Acquire) * 2) % audio_buffer.len();
-                                    let mut count = spare * 2;
-                                    if start + count > audio_buffer.len() {
-                                        (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(start, audio_buffer.len()));
-                                        //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(start, audio_buffer.len()));
-                                        count -= audio_buffer.len() - start;
-                                        (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(0, count));
-                                        //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(0, count));
-                                    } else {
-                                        (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(start, start + count));
-                                        //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(start, (start + count)));
-                                    }*/
-                                    write_wrapped(&mut xsynth, write_pos.load(Ordering::Relaxed), spare);
-                                    samples -= spare;
-                                    write_pos.fetch_add(spare, Ordering::Relaxed);
-                                    //*write_pos.lock().unwrap() = write;
+                        let offset = if audio_fps > 0.0 {
+                            f64::floor(ev_time * audio_fps) / audio_fps - start_time
+                        } else {
+                            ev_time - start_time
+                        };
+
+                        let samples = (offset * sample_rate) as isize - write_pos.load(Ordering::Relaxed) as isize;
+                        if samples > 0 {
+                            let mut samples = samples as usize;
+                            while write_pos.load(Ordering::Relaxed) + samples > read_pos.load(Ordering::Relaxed) + buff_len / 2 {
+                                let mut spare = (read_pos.load(Ordering::Relaxed) + buff_len / 2) - write_pos.load(Ordering::Relaxed);
+                                if spare > 0 {
+                                    if spare > samples { spare = samples; }
+                                    if spare != 0 {
+                                        /*let start = (write_pos.load(Ordering::Acquire) * 2) % audio_buffer.len();
+                                        let mut count = spare * 2;
+                                        if start + count > audio_buffer.len() {
+                                            (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(start, audio_buffer.len()));
+                                            //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(start, audio_buffer.len()));
+                                            count -= audio_buffer.len() - start;
+                                            (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(0, count));
+                                            //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(0, count));
+                                        } else {
+                                            (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(start, start + count));
+                                            //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(start, (start + count)));
+                                        }*/
+                                        write_wrapped(&mut xsynth, write_pos.load(Ordering::Relaxed), spare);
+                                        samples -= spare;
+                                        write_pos.fetch_add(spare, Ordering::Relaxed);
+                                        //*write_pos.lock().unwrap() = write;
+                                    }
+                                    if samples == 0 { break; }
+                                }
+                                std::thread::sleep(std::time::Duration::from_millis(2));
+                                if reset_requested.load(Ordering::Relaxed) {
+                                    break;
                                 }
-                                if samples == 0 { break; }
                             }
-                            std::thread::sleep(std::time::Duration::from_millis(2));
-                            if reset_requested.load(Ordering::Relaxed) {
-                                break;
+                            if samples != 0 {
+                                /*let start = (write_pos.load(Ordering::Acquire) * 2) % audio_buffer.len();
+                                let mut count = samples * 2;
+                                if start + count > audio_buffer.len() {
+                                    (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(start, audio_buffer.len()));
+                                    //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(start, audio_buffer.len()));
+                                    count -= audio_buffer.len() - start;
+                                    (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(0, count));
+                                    //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(0, count));
+                                } else {
+                                    (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(start, start + count));
+                                    //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(start, (start + count)));
+                                }*/
+                                write_wrapped(&mut xsynth, write_pos.load(Ordering::Relaxed), samples);
                             }
+                            write_pos.fetch_add(samples, Ordering::Relaxed);
                         }
-                        if samples != 0 {
-                            /*let start = (write_pos.load(Ordering::Acquire) * 2) % audio_buffer.len();
-                            let mut count = samples * 2;
-                            if start + count > audio_buffer.len() {
-                                (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(start, audio_buffer.len()));
-                                //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(start, audio_buffer.len()));
-                                count -= audio_buffer.len() - start;
-                                (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(0, count));
-                                //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(0, count));
-                            } else {
-                                (*xsynth).read_samples_unchecked(audio_buffer.slice_mut(start, start + count));
-                                //limiter.lock().unwrap().apply_limiter(audio_buffer.slice_mut(start, (start + count)));
-                            }*/
-                            write_wrapped(&mut xsynth, write_pos.load(Ordering::Relaxed), samples);
-                        }
-                        write_pos.fetch_add(samples, Ordering::Relaxed);
-                    }
 
-                    match e.command {
-                        MIDIEventType::NoteOn => {
-                            let mut key = e.data[1];
-                            if (key as i32) < -transpose { continue; }
-                            key = (key as i32 + transpose) as u8;
+                        match kind {
+                            MIDIEventType::NoteOn => {
+                                let mut key = e.data[0];
+                                if (key as i32) < -transpose { continue; }
+                                key = (key as i32 + transpose) as u8;
                             
-                            let vel = e.data[2];
-                            if vel < get_skipping_velocity(write_pos.load(Ordering::Relaxed), read_pos.load(Ordering::Relaxed)) { continue; }
-                            if vel < 15 { continue; }
-
-                            (*xsynth).send_event(
-                                SynthEvent::Channel(e.data[0] as u32, 
-                                    ChannelEvent::Audio(ChannelAudioEvent::NoteOn {
-                                        key,
-                                        vel
-                                    })
-                                )
-                            );
-                        },
-                        MIDIEventType::NoteOff => {
-                            let mut key = e.data[1];
-                            if (key as i32) < -transpose { continue; }
-                            key = (key as i32 + transpose) as u8;
-
-                            let vel = e.data[2];
-                            if vel < get_skipping_velocity(write_pos.load(Ordering::Relaxed), read_pos.load(Ordering::Relaxed)) { continue; }
-                            if vel < 15 { continue; }
-
-                            (*xsynth).send_event(
-                                SynthEvent::Channel(e.data[0] as u32, 
-                                    ChannelEvent::Audio(ChannelAudioEvent::NoteOff {
-                                        key
-                                    }
-                                )
-                            ));
-                        },
-                        MIDIEventType::ControlEvent => {
-                            let num = e.data[1];
-                            let val = e.data[2];
-                            (*xsynth).send_event(
-                                SynthEvent::Channel(e.data[0] as u32, 
-                                    ChannelEvent::Audio(ChannelAudioEvent::Control(
-                                        ControlEvent::Raw(num, val)
+                                let vel = e.data[1];
+                                if vel < get_skipping_velocity(write_pos.load(Ordering::Relaxed), read_pos.load(Ordering::Relaxed)) { continue; }
+                                if vel < 15 { continue; }
+
+                                (*xsynth).send_event(
+                                    SynthEvent::Channel(e.full_channel() as u32, 
+                                        ChannelEvent::Audio(ChannelAudioEvent::NoteOn {
+                                            key,
+                                            vel
+                                        })
+                                    )
+                                );
+                            },
+                            MIDIEventType::NoteOff => {
+                                let mut key = e.data[0];
+                                if (key as i32) < -transpose { continue; }
+                                key = (key as i32 + transpose) as u8;
+
+                                let vel = e.data[1];
+                                if vel < get_skipping_velocity(write_pos.load(Ordering::Relaxed), read_pos.load(Ordering::Relaxed)) { continue; }
+                                if vel < 15 { continue; }
+
+                                (*xsynth).send_event(
+                                    SynthEvent::Channel(e.full_channel() as u32, 
+                                        ChannelEvent::Audio(ChannelAudioEvent::NoteOff {
+                                            key
+                                        }
                                     )
-                                )
-                            ));
-                        },
-                        MIDIEventType::PitchBend => {
-                            let v1 = e.data[1];
-                            let v2 = e.data[2];
-                            let bend = (((v2 as i32) << 7) | v1 as i32) as f32 - 8192.0;
-                            (*xsynth).send_event(
-                                SynthEvent::Channel(e.data[0] as u32,
-                                    ChannelEvent::Audio(ChannelAudioEvent::Control(
-                                        ControlEvent::PitchBendValue(bend / 8192.0)
+                                ));
+                            },
+                            MIDIEventType::ControlEvent => {
+                                let num = e.data[0];
+                                let val = e.data[1];
+                                (*xsynth).send_event(
+                                    SynthEvent::Channel(e.full_channel() as u32, 
+                                        ChannelEvent::Audio(ChannelAudioEvent::Control(
+                                            ControlEvent::Raw(num, val)
+                                        )
                                     )
-                                )
-                            ));
+                                ));
+                            },
+                            MIDIEventType::PitchBend => {
+                                let v1 = e.data[0];
+                                let v2 = e.data[1];
+                                let bend = (((v2 as i32) << 7) | v1 as i32) as f32 - 8192.0;
+                                (*xsynth).send_event(
+                                    SynthEvent::Channel(e.full_channel() as u32,
+                                        ChannelEvent::Audio(ChannelAudioEvent::Control(
+                                            ControlEvent::PitchBendValue(bend / 8192.0)
+                                        )
+                                    )
+                                ));
+                            },
+                            MIDIEventType::ProgramChange => {
+                                (*xsynth).send_event(
+                                    SynthEvent::Channel(e.full_channel() as u32,
+                                        ChannelEvent::Audio(ChannelAudioEvent::ProgramChange(e.data[0]))
+                                    )
+                                );
+                            },
+                            MIDIEventType::SysEx => {
+                                // resetting the controllers also puts every channel back on program 0
+                                if SysExKind::from_raw(e.data[0]) != SysExKind::Other {
+                                    (*xsynth).send_event(SynthEvent::AllChannels(ChannelEvent::Audio(ChannelAudioEvent::AllNotesOff)));
+                                    (*xsynth).send_event(SynthEvent::AllChannels(ChannelEvent::Audio(ChannelAudioEvent::ResetControl)));
+                                }
+                            },
+                            // xsynth has nothing for aftertouch
+                            MIDIEventType::PolyAftertouch | MIDIEventType::ChannelPressure => {}
                         }
-                    }
 
-                    if reset_requested.load(Ordering::Relaxed) {
-                        break;
+                        if reset_requested.load(Ordering::Relaxed) {
+                            break 'events;
+                        }
                     }
                 }
 
@@ -511,9 +622,9 @@ impl PrerenderAudio {
         }
     }
 
-    pub fn start(&mut self, start_time: f32, speed: f32) -> () {
+    pub fn start(&mut self, start_time: f64, speed: f32) -> () {
         self.kill_last_generator();
-        self.start_time = start_time / speed;
+        self.start_time = start_time / speed as f64;
         self.generator_thread = Some(self.render_audio(self.start_time, speed));
     }
 
@@ -525,12 +636,12 @@ impl PrerenderAudio {
         self.write_pos.store(0, Ordering::Relaxed);
     }
 
-    pub fn sync_player(&mut self, time: f32, speed: f32) -> () {
+    pub fn sync_player(&mut self, time: f64, speed: f32) -> () {
         let mut read_pos = self.read_pos.load(Ordering::Relaxed);
-        let time = time / speed;
-        let t = self.start_time + (read_pos as f32) / self.sample_rate;
+        let time = time / speed as f64;
+        let t = self.start_time + (read_pos as f64) / self.sample_rate as f64;
         let offs = time - t;
-        let mut new_pos = read_pos as i32 + (offs * self.sample_rate) as i32;
+        let mut new_pos = read_pos as i32 + (offs * self.sample_rate as f64) as i32;
         if new_pos < 0 {
             new_pos = 0;
         }
@@ -591,7 +702,7 @@ impl PrerenderAudio {
         stream
     }
 
-    pub fn play_audio(&mut self, time: f32, speed: f32, mut force: bool) -> () {
+    pub fn play_audio(&mut self, time: f64, speed: f32, mut force: bool) -> () {
         //let mut g_time = self.g_time.clone();
         
         if !force {
@@ -1,4 +1,16 @@
 pub mod byte_reader;
 pub mod midi_file;
 pub mod buffered_byte_reader;
-pub mod midi_track_parser;
\ No newline at end of file
+pub mod midi_track_parser;
+pub mod midi_error;
+pub mod mapped_byte_reader;
+pub mod track_reader;
+pub mod midi_archive;
+pub mod track_splitter;
+pub mod midi_cache;
+pub mod load_progress;
+pub mod midi_stream;
+pub mod note_filter;
+pub mod midi_writer;
+pub mod midi_validator;
+pub mod synth_pairing;
\ No newline at end of file
@@ -1,20 +1,21 @@
-use std::{fs::File, io::Read};
-use std::io::{self, Seek};
+use std::fs::File;
+use std::io::{self, Read, Seek};
 use std::sync::{Arc, Mutex};
 
-pub struct BufferedByteReader {
-    pub file_stream: Arc<Mutex<File>>,
+use crate::midi::midi_error::MidiLoadError;
+
+pub struct BufferedByteReader<R = File> {
+    pub file_stream: Arc<Mutex<R>>,
     start: usize,
     len: usize,
-    buf_size: usize,
     pub pos: usize,
     buf_start: usize,
     buf_pos: usize,
     buf: Vec<u8>
 }
 
-impl BufferedByteReader {
-    pub fn new(stream: Arc<Mutex<File>>, start: usize, len: usize, buf_size: usize) -> Result<Self, ()> {
+impl<R: Read + Seek> BufferedByteReader<R> {
+    pub fn new(stream: Arc<Mutex<R>>, start: usize, len: usize, buf_size: usize) -> Result<Self, MidiLoadError> {
         let mut buffer_length = buf_size;
         if buffer_length > len { buffer_length = len; }
         
@@ -22,43 +23,50 @@ impl BufferedByteReader {
             file_stream: stream,
             start,
             len,
-            buf_size,
             pos: start,
             buf_start: 0,
             buf_pos: 0,
             buf: vec![0; buffer_length]
         };
         
-        bbr.update_buffer().unwrap();
+        bbr.update_buffer()?;
 
         Ok(bbr)
     }
 
-    fn update_buffer(&mut self) -> Result<(),&str> {
-        let mut read = self.buf_size as usize;
+    fn truncated(&self) -> MidiLoadError {
+        MidiLoadError::TruncatedTrack { track: None, pos: self.pos as u64 }
+    }
+
+    fn update_buffer(&mut self) -> Result<(), MidiLoadError> {
+        let mut read = self.buf.len();
 
         if (self.pos + read) > (self.start + self.len) {
             read = self.start + self.len - self.pos;
         }
 
-        if read == 0 && self.buf_size != 0 {
-            panic!("outside buffer");
+        self.buf_start = self.pos;
+        self.buf_pos = 0usize;
+
+        // nothing left to buffer, reads from here on will fail anyway
+        if read == 0 {
+            return Ok(())
         }
 
         // lol
         {
             let mut strm = self.file_stream.lock().unwrap();
-            strm.seek(io::SeekFrom::Start(self.pos as u64)).unwrap();
-            strm.read(&mut self.buf).unwrap();
+            strm.seek(io::SeekFrom::Start(self.pos as u64))?;
+            strm.read_exact(&mut self.buf[..read]).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => self.truncated(),
+                _ => MidiLoadError::Io(e)
+            })?;
         }
 
-        self.buf_start = self.pos;
-        self.buf_pos = 0usize;
-
         Ok(())
     }
 
-    pub fn seek(&mut self, offset: isize, origin: i32) -> Result<(), &str> {
+    pub fn seek(&mut self, offset: isize, origin: i32) -> Result<(), MidiLoadError> {
         let mut real_offs: isize = offset;
         if origin == 0 {
             real_offs += self.start as isize;
@@ -66,55 +74,65 @@ impl BufferedByteReader {
             real_offs += self.pos as isize;
         }
 
+        // only a broken track can send it back past its own start
         if real_offs < self.start as isize {
-            panic!("seek before start")
+            return Err(MidiLoadError::TruncatedTrack { track: None, pos: self.start as u64 })
         }
         if real_offs > (self.start + self.len) as isize {
-            panic!("seek past end")
+            return Err(MidiLoadError::TruncatedTrack { track: None, pos: (self.start + self.len) as u64 })
         }
 
         self.pos = real_offs as usize;
 
-        if self.buf_start as isize <= real_offs && (real_offs) < (self.buf_start + self.buf_size) as isize {
+        if self.buf_start as isize <= real_offs && (real_offs) < (self.buf_start + self.buf.len()) as isize {
             self.buf_pos = self.pos - self.buf_start;
             return Ok(())
         }
 
-        self.update_buffer().unwrap();
+        self.update_buffer()?;
 
         Ok(())
     }
 
-    pub fn read(&mut self, dst: &mut [u8], size: usize) -> Result<(), &str> {
+    pub fn read(&mut self, dst: &mut [u8], size: usize) -> Result<(), MidiLoadError> {
         if self.pos + size > self.start + self.len {
-            panic!("read past end | requested read: {}, buff size: {}", self.pos + size, self.start + self.len);
-            //return Err(format!("read past end | requested read: {}, buff size: {}", self.pos + size, self.start + self.len))
-        }
-        if size > self.buf_size as usize {
-            //panic!("unimplemented; read size larger than buffer size");
-            return Err("unimplemented; read size larger than buffer size")
+            return Err(self.truncated())
         }
 
-        if self.buf_start + self.buf_pos + size > self.buf_start + self.buf_size as usize {
-            self.update_buffer().unwrap();
+        // reads bigger than the buffer (long sysex / text events) get copied over in pieces
+        let mut copied = 0;
+        while copied < size {
+            if self.buf_pos >= self.buf.len() {
+                self.update_buffer()?;
+            }
+            let avail = (self.buf.len() - self.buf_pos).min(self.start + self.len - self.pos);
+            let count = avail.min(size - copied);
+
+            // skull emoji
+            dst[copied..copied+count].clone_from_slice(&self.buf[self.buf_pos..self.buf_pos+count]);
+            self.pos += count;
+            self.buf_pos += count;
+            copied += count;
         }
 
-        // skull emoji
-        dst[..size].clone_from_slice(&self.buf[self.buf_pos..self.buf_pos+size]);
-        self.pos += size;
-        self.buf_pos += size;
-
         Ok(())
     }
 
-    pub fn read_byte(&mut self) -> Result<u8,()> {
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.start + self.len
+    }
+
+    pub fn remaining(&self) -> usize {
+        (self.start + self.len).saturating_sub(self.pos)
+    }
+
+    pub fn read_byte(&mut self) -> Result<u8, MidiLoadError> {
         let mut ret: [u8; 1] = [0];
-        self.read(&mut ret, 1).unwrap();
+        self.read(&mut ret, 1)?;
         Ok(ret[0])
     }
 
-    pub fn skip_bytes(&mut self, size: usize) -> Result<(),()> {
-        self.seek(size as isize, 1).unwrap();
-        Ok(())
+    pub fn skip_bytes(&mut self, size: usize) -> Result<(), MidiLoadError> {
+        self.seek(size as isize, 1)
     }
 }
\ No newline at end of file
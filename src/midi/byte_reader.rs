@@ -1,13 +1,20 @@
-use std::{fs::File, io::Read};
+use std::io::Read;
 use std::io;
 
-pub fn read_u32(stream: &mut File) -> io::Result<u32> {
+pub fn read_u32<R: Read>(stream: &mut R) -> io::Result<u32> {
     let mut buf: [u8; 4] = [0; 4];
     stream.read_exact(&mut buf[..])?;
     Ok(u32::from_be_bytes(buf))
 }
 
-pub fn read_u16(stream: &mut File) -> io::Result<u16> {
+// RIFF chunk lengths are little endian
+pub fn read_u32_le<R: Read>(stream: &mut R) -> io::Result<u32> {
+    let mut buf: [u8; 4] = [0; 4];
+    stream.read_exact(&mut buf[..])?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub fn read_u16<R: Read>(stream: &mut R) -> io::Result<u16> {
     let mut buf: [u8; 2] = [0, 0];
     stream.read_exact(&mut buf[..])?;
     Ok(u16::from_be_bytes(buf))
@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadStage {
+    Opening = 0,
+    // compressed midis get unpacked into a temp file before anything can be read
+    Unpacking,
+    Parsing,
+    Converting,
+    Merging,
+    Caching
+}
+
+// shared between the loading thread and the ui. the loader only ever adds to it,
+// the ui reads it every frame and can ask the loader to stop
+pub struct LoadProgress {
+    stage: AtomicU8,
+    pub track_count: AtomicUsize,
+    pub tracks_done: AtomicUsize,
+    pub bytes_total: AtomicU64,
+    pub bytes_read: AtomicU64,
+    pub keys_merged: AtomicUsize,
+    cancelled: AtomicBool
+}
+
+impl LoadProgress {
+    pub fn new() -> Self {
+        Self {
+            stage: AtomicU8::new(LoadStage::Opening as u8),
+            track_count: AtomicUsize::new(0),
+            tracks_done: AtomicUsize::new(0),
+            bytes_total: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            keys_merged: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false)
+        }
+    }
+
+    pub fn stage(&self) -> LoadStage {
+        match self.stage.load(Ordering::Relaxed) {
+            0 => LoadStage::Opening,
+            1 => LoadStage::Unpacking,
+            2 => LoadStage::Parsing,
+            3 => LoadStage::Converting,
+            4 => LoadStage::Merging,
+            _ => LoadStage::Caching
+        }
+    }
+
+    // tracks_done and bytes_read count per stage, so they start over
+    pub fn set_stage(&self, stage: LoadStage) -> () {
+        self.tracks_done.store(0, Ordering::Relaxed);
+        self.bytes_read.store(0, Ordering::Relaxed);
+        self.stage.store(stage as u8, Ordering::Relaxed);
+    }
+
+    pub fn cancel(&self) -> () {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // how far along the current stage is, 0 to 1
+    pub fn fraction(&self) -> f32 {
+        let ratio = |done: u64, total: u64| if total == 0 { 0.0 } else { (done as f64 / total as f64).min(1.0) as f32 };
+        match self.stage() {
+            LoadStage::Opening | LoadStage::Caching => 0.0,
+            LoadStage::Unpacking | LoadStage::Parsing => ratio(self.bytes_read.load(Ordering::Relaxed), self.bytes_total.load(Ordering::Relaxed)),
+            LoadStage::Converting => ratio(self.tracks_done.load(Ordering::Relaxed) as u64, self.track_count.load(Ordering::Relaxed) as u64),
+            LoadStage::Merging => ratio(self.keys_merged.load(Ordering::Relaxed) as u64, 256)
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let tracks = format!("{}/{} tracks",
+            self.tracks_done.load(Ordering::Relaxed),
+            self.track_count.load(Ordering::Relaxed));
+        match self.stage() {
+            LoadStage::Opening => String::from("Opening..."),
+            LoadStage::Unpacking => format!("Unpacking: {:.1}/{:.1} MB",
+                self.bytes_read.load(Ordering::Relaxed) as f64 / (1024.0 * 1024.0),
+                self.bytes_total.load(Ordering::Relaxed) as f64 / (1024.0 * 1024.0)),
+            LoadStage::Parsing => format!("Parsing: {}, {:.1}/{:.1} MB",
+                tracks,
+                self.bytes_read.load(Ordering::Relaxed) as f64 / (1024.0 * 1024.0),
+                self.bytes_total.load(Ordering::Relaxed) as f64 / (1024.0 * 1024.0)),
+            LoadStage::Converting => format!("Converting times: {}", tracks),
+            LoadStage::Merging => format!("Merging: {}/256 keys", self.keys_merged.load(Ordering::Relaxed)),
+            LoadStage::Caching => String::from("Writing to the cache...")
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use crate::midi::midi_error::MidiLoadError;
+
+// reads a track straight out of a memory mapped file. every track gets its own cursor into the
+// same mapping, so there's no lock to fight over and nothing to copy
+pub struct MappedByteReader {
+    data: Arc<Mmap>,
+    start: usize,
+    len: usize,
+    pub pos: usize
+}
+
+impl MappedByteReader {
+    pub fn new(data: Arc<Mmap>, start: usize, len: usize) -> Self {
+        // the track locations are already clamped to the file, but don't trust that blindly
+        let len = len.min(data.len().saturating_sub(start));
+        Self {
+            data,
+            start,
+            len,
+            pos: start
+        }
+    }
+
+    fn truncated(&self) -> MidiLoadError {
+        MidiLoadError::TruncatedTrack { track: None, pos: self.pos as u64 }
+    }
+
+    pub fn seek(&mut self, offset: isize, origin: i32) -> Result<(), MidiLoadError> {
+        let mut real_offs: isize = offset;
+        if origin == 0 {
+            real_offs += self.start as isize;
+        } else {
+            real_offs += self.pos as isize;
+        }
+
+        // only a broken track can send it back past its own start
+        if real_offs < self.start as isize {
+            return Err(MidiLoadError::TruncatedTrack { track: None, pos: self.start as u64 })
+        }
+        if real_offs > (self.start + self.len) as isize {
+            return Err(MidiLoadError::TruncatedTrack { track: None, pos: (self.start + self.len) as u64 })
+        }
+
+        self.pos = real_offs as usize;
+        Ok(())
+    }
+
+    pub fn read(&mut self, dst: &mut [u8], size: usize) -> Result<(), MidiLoadError> {
+        if self.pos + size > self.start + self.len {
+            return Err(self.truncated())
+        }
+
+        dst[..size].copy_from_slice(&self.data[self.pos..self.pos+size]);
+        self.pos += size;
+        Ok(())
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.start + self.len
+    }
+
+    pub fn remaining(&self) -> usize {
+        (self.start + self.len).saturating_sub(self.pos)
+    }
+
+    pub fn read_byte(&mut self) -> Result<u8, MidiLoadError> {
+        if self.at_end() {
+            return Err(self.truncated())
+        }
+        let b = self.data[self.pos];
+        self.pos += 1;
+        Ok(b)
+    }
+
+    pub fn skip_bytes(&mut self, size: usize) -> Result<(), MidiLoadError> {
+        self.seek(size as isize, 1)
+    }
+}
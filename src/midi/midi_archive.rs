@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::Ordering;
+
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
+
+use crate::midi::load_progress::{LoadProgress, LoadStage};
+use crate::midi::midi_error::MidiLoadError;
+
+const MIDI_EXTENSIONS: [&str; 5] = ["mid", "midi", "rmi", "smf", "kar"];
+// how much gets unpacked between progress updates and checks for cancelling
+const UNPACK_BLOCK: usize = 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Zip
+}
+
+// goes by the magic bytes instead of the extension, people rename these all the time
+pub fn detect(file: &mut File) -> io::Result<Compression> {
+    let mut magic = [0u8; 6];
+    let mut read = 0;
+    while read < magic.len() {
+        let n = file.read(&mut magic[read..])?;
+        if n == 0 { break; }
+        read += n;
+    }
+    file.seek(SeekFrom::Start(0))?;
+
+    Ok(match &magic[..read] {
+        [0x1F, 0x8B, ..] => Compression::Gzip,
+        [0xFD, b'7', b'z', b'X', b'Z', 0x00] => Compression::Xz,
+        [b'P', b'K', 0x03, 0x04, ..] => Compression::Zip,
+        _ => Compression::None
+    })
+}
+
+pub fn is_midi_name(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    MIDI_EXTENSIONS.iter().any(|ext| lower.ends_with(&format!(".{}", ext)))
+}
+
+fn zip_err(e: zip::result::ZipError) -> MidiLoadError {
+    match e {
+        zip::result::ZipError::Io(e) => MidiLoadError::Io(e),
+        e => MidiLoadError::Archive(e.to_string())
+    }
+}
+
+// names of every midi inside a zip, in archive order. empty if the file isn't a zip
+pub fn zip_midi_entries(path: &str) -> Result<Vec<String>, MidiLoadError> {
+    let mut file = File::open(path)?;
+    if detect(&mut file)? != Compression::Zip {
+        return Ok(Vec::new())
+    }
+
+    let archive = ZipArchive::new(file).map_err(zip_err)?;
+    Ok(archive.file_names()
+        .filter(|name| is_midi_name(name))
+        .map(String::from)
+        .collect())
+}
+
+// counts the compressed bytes the decoders pull out of the file, that's the only size known up front
+struct CountingReader<'a, R> {
+    inner: R,
+    progress: &'a LoadProgress
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for CountingReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+// opens a midi, unpacking it into an anonymous temp file first if it's compressed. the temp
+// file gets mmapped like any other file and is deleted once it's closed.
+// zip_entry picks the midi out of a zip, otherwise the first one in it is used
+pub fn open(path: &str, zip_entry: Option<&str>, progress: &LoadProgress) -> Result<File, MidiLoadError> {
+    let mut file = File::open(path)?;
+    let compression = detect(&mut file)?;
+    if compression == Compression::None {
+        return Ok(file)
+    }
+
+    progress.set_stage(LoadStage::Unpacking);
+    progress.bytes_total.store(file.metadata()?.len(), Ordering::Relaxed);
+    let counted = CountingReader { inner: file, progress };
+    match compression {
+        Compression::Gzip => unpack(MultiGzDecoder::new(BufReader::new(counted)), progress),
+        Compression::Xz => unpack(XzDecoder::new_multi_decoder(BufReader::new(counted)), progress),
+        _ => {
+            let mut archive = ZipArchive::new(counted).map_err(zip_err)?;
+            let name = match zip_entry {
+                Some(name) => String::from(name),
+                None => archive.file_names()
+                    .find(|name| is_midi_name(name))
+                    .map(String::from)
+                    .ok_or_else(|| MidiLoadError::Archive(String::from("there are no MIDI files in this archive")))?
+            };
+            let entry = archive.by_name(&name).map_err(zip_err)?;
+            // reading the central directory doesn't count, only the entry itself
+            progress.bytes_total.store(entry.compressed_size(), Ordering::Relaxed);
+            progress.bytes_read.store(0, Ordering::Relaxed);
+            unpack(entry, progress)
+        }
+    }
+}
+
+fn unpack<D: Read>(mut decoder: D, progress: &LoadProgress) -> Result<File, MidiLoadError> {
+    let mut tmp = tempfile::tempfile()?;
+    let mut buf = vec![0u8; UNPACK_BLOCK];
+    loop {
+        if progress.is_cancelled() {
+            return Err(MidiLoadError::Cancelled);
+        }
+        let n = match decoder.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            // corrupt streams show up as these from flate2 / xz2
+            Err(e) => return Err(match e.kind() {
+                io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::UnexpectedEof =>
+                    MidiLoadError::Archive(e.to_string()),
+                _ => MidiLoadError::Io(e)
+            })
+        };
+        tmp.write_all(&buf[..n])?;
+    }
+    tmp.seek(SeekFrom::Start(0))?;
+    Ok(tmp)
+}
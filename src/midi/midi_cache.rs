@@ -0,0 +1,396 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::midi::midi_file::TimeDivision;
+use crate::midi::midi_track_parser::{MIDIEvent, MetaEvent, MetaEventName, Note, NotePairing, ParseMode, TempoEvent, MAX_PORTS};
+use crate::midi::note_filter::NoteFilter;
+
+const CACHE_DIR: &str = "./cache";
+const CACHE_EXT: &str = "kmc";
+const MAGIC: [u8; 4] = *b"KMCF";
+// bump whenever the layout below or anything that ends up in it changes
+const CACHE_VERSION: u32 = 9;
+
+// how much of the file gets hashed for the key. reading all of a multi gigabyte midi just to
+// find out it's cached would defeat the point, so only the ends and a few spots in between
+const SAMPLE_LEN: u64 = 64 * 1024;
+const SAMPLE_COUNT: u64 = 16;
+
+// decoding goes through a buffer of this many records at a time
+const RECORD_BATCH: usize = 65536;
+
+// everything open_midi needs after a midi is parsed and merged, which is also exactly what gets cached
+pub struct CachedMIDI {
+    pub division: TimeDivision,
+    pub key_range: [u8; 2],
+    pub port_count: u8,
+    pub note_counts: Vec<u64>,
+    pub track_names: Vec<String>,
+    pub notes_removed: u64,
+    pub warnings: Vec<String>,
+    pub tempo_evs: Vec<TempoEvent>,
+    pub midi_evs: Vec<MIDIEvent>,
+    pub meta_evs: Vec<MetaEvent>,
+    pub notes: Vec<Vec<Note>>
+}
+
+// FNV-1a, so keys stay the same between builds (std's hasher makes no such promise)
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> () {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+// identifies a midi together with the options it was parsed with. anything that changes
+// the parsed result has to go in here
+pub fn cache_key(path: &str, zip_entry: Option<&str>, tick_based: bool, parse_mode: ParseMode, note_pairing: NotePairing, note_filter: NoteFilter) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let meta = file.metadata()?;
+    let size = meta.len();
+    let mtime = meta.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+
+    let mut h = Fnv::new();
+    h.write(&CACHE_VERSION.to_le_bytes());
+    h.write(&size.to_le_bytes());
+    h.write(&mtime.to_le_bytes());
+    h.write(&[tick_based as u8, (parse_mode == ParseMode::Lenient) as u8, note_pairing as u8]);
+    h.write(&[note_filter.remove_duplicates as u8, note_filter.min_velocity]);
+    h.write(&note_filter.min_length.to_le_bytes());
+    if let Some(entry) = zip_entry {
+        h.write(entry.as_bytes());
+    }
+
+    let mut buf = Vec::with_capacity(SAMPLE_LEN as usize);
+    let step = size.saturating_sub(SAMPLE_LEN) / SAMPLE_COUNT;
+    let mut positions: Vec<u64> = (0..SAMPLE_COUNT).map(|i| i * step).collect();
+    positions.push(size.saturating_sub(SAMPLE_LEN));
+    positions.dedup();
+    for pos in positions {
+        file.seek(SeekFrom::Start(pos))?;
+        let n = (&mut file).take(SAMPLE_LEN).read_to_end(&mut buf)?;
+        h.write(&buf[..n]);
+        buf.clear();
+    }
+
+    Ok(h.0)
+}
+
+fn cache_path(key: u64) -> PathBuf {
+    Path::new(CACHE_DIR).join(format!("{:016x}.{}", key, CACHE_EXT))
+}
+
+fn cache_files() -> Vec<(PathBuf, u64, SystemTime)> {
+    let Ok(dir) = fs::read_dir(CACHE_DIR) else {
+        return Vec::new();
+    };
+    dir.filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().map_or(false, |ext| ext == CACHE_EXT))
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            Some((e.path(), meta.len(), meta.modified().unwrap_or(UNIX_EPOCH)))
+        })
+        .collect()
+}
+
+// total size of the cache directory in bytes
+pub fn size() -> u64 {
+    cache_files().iter().map(|(_, len, _)| len).sum()
+}
+
+pub fn clear() -> io::Result<()> {
+    for (path, _, _) in cache_files() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// deletes the least recently used files until the cache fits in limit bytes
+fn enforce_limit(limit: u64) -> io::Result<()> {
+    let mut files = cache_files();
+    files.sort_by_key(|(_, _, modified)| *modified);
+
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    for (path, len, _) in files {
+        if total <= limit {
+            break;
+        }
+        fs::remove_file(path)?;
+        total -= len;
+    }
+    Ok(())
+}
+
+// None if there's nothing cached under key. a file that can't be read is thrown away
+pub fn load(key: u64) -> Option<CachedMIDI> {
+    let path = cache_path(key);
+    let file = File::open(&path).ok()?;
+
+    match read_cache(&mut BufReader::with_capacity(1 << 20, &file), key) {
+        Ok(cached) => {
+            // hits count as uses, enforce_limit goes by this
+            if let Ok(f) = File::options().write(true).open(&path) {
+                let _ = f.set_modified(SystemTime::now());
+            }
+            Some(cached)
+        }
+        Err(e) => {
+            println!("discarding cache file {}: {}", path.display(), e);
+            drop(file);
+            let _ = fs::remove_file(&path);
+            None
+        }
+    }
+}
+
+// writes the cache file for key, unless it alone is bigger than limit bytes
+pub fn store(key: u64, cached: &CachedMIDI, limit: u64) -> io::Result<()> {
+    let approx_size = cached.notes.iter().map(|n| n.len() as u64 * 16).sum::<u64>()
+        + cached.midi_evs.len() as u64 * 11
+        + cached.tempo_evs.len() as u64 * 20;
+    if approx_size > limit {
+        println!("not caching, {} bytes is over the cache limit", approx_size);
+        return Ok(());
+    }
+
+    fs::create_dir_all(CACHE_DIR)?;
+    let path = cache_path(key);
+    // written under another name first so a half written file is never picked up
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut w = BufWriter::with_capacity(1 << 20, File::create(&tmp_path)?);
+        write_cache(&mut w, key, cached)?;
+        w.flush()?;
+    }
+    fs::rename(&tmp_path, &path)?;
+
+    enforce_limit(limit)
+}
+
+fn write_cache<W: Write>(w: &mut W, key: u64, c: &CachedMIDI) -> io::Result<()> {
+    w.write_all(&MAGIC)?;
+    w.write_all(&CACHE_VERSION.to_le_bytes())?;
+    w.write_all(&key.to_le_bytes())?;
+
+    match c.division {
+        TimeDivision::PPQ(ppq) => {
+            w.write_all(&[0])?;
+            w.write_all(&ppq.to_le_bytes())?;
+        }
+        TimeDivision::SMPTE { fps, ticks_per_frame } => {
+            w.write_all(&[1, fps, ticks_per_frame])?;
+        }
+    }
+    w.write_all(&c.key_range)?;
+    w.write_all(&[c.port_count])?;
+
+    write_records(w, &c.note_counts, |n| n.to_le_bytes())?;
+    write_strings(w, &c.track_names)?;
+    w.write_all(&c.notes_removed.to_le_bytes())?;
+    write_strings(w, &c.warnings)?;
+
+    write_records(w, &c.tempo_evs, |t| {
+        let mut b = [0u8; 20];
+        b[0..8].copy_from_slice(&t.time.to_le_bytes());
+        b[8..16].copy_from_slice(&t.time_norm.to_le_bytes());
+        b[16..20].copy_from_slice(&t.tempo.to_le_bytes());
+        b
+    })?;
+    write_records(w, &c.midi_evs, |e| {
+        let mut b = [0u8; 14];
+        b[0..8].copy_from_slice(&e.time.to_le_bytes());
+        b[8] = e.status;
+        b[9..11].copy_from_slice(&e.data);
+        b[11] = e.port;
+        b[12..14].copy_from_slice(&e.track.to_le_bytes());
+        b
+    })?;
+
+    w.write_all(&(c.meta_evs.len() as u64).to_le_bytes())?;
+    for ev in c.meta_evs.iter() {
+        w.write_all(&ev.time.to_le_bytes())?;
+        w.write_all(&[ev.meta_name as u8])?;
+        w.write_all(&ev.track.to_le_bytes())?;
+        w.write_all(&(ev.data.len() as u64).to_le_bytes())?;
+        w.write_all(&ev.data)?;
+    }
+
+    w.write_all(&(c.notes.len() as u64).to_le_bytes())?;
+    for key_notes in c.notes.iter() {
+        write_records(w, key_notes, |n| {
+            let (start, duration, info) = n.to_raw();
+            let mut b = [0u8; 16];
+            b[0..8].copy_from_slice(&start.to_le_bytes());
+            b[8..12].copy_from_slice(&duration.to_le_bytes());
+            b[12..16].copy_from_slice(&info.to_le_bytes());
+            b
+        })?;
+    }
+
+    Ok(())
+}
+
+fn read_cache<Rd: Read>(r: &mut Rd, key: u64) -> io::Result<CachedMIDI> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if magic != MAGIC || read_u32(r)? != CACHE_VERSION || read_u64(r)? != key {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a cache file for this midi or an old version"));
+    }
+
+    let mut div = [0u8; 3];
+    r.read_exact(&mut div)?;
+    let division = match div[0] {
+        0 => TimeDivision::PPQ(u16::from_le_bytes([div[1], div[2]])),
+        _ => TimeDivision::SMPTE { fps: div[1], ticks_per_frame: div[2] }
+    };
+    let mut key_range = [0u8; 2];
+    r.read_exact(&mut key_range)?;
+    let mut port_count = [0u8; 1];
+    r.read_exact(&mut port_count)?;
+
+    let note_counts = read_records(r, |b: &[u8; 8]| u64::from_le_bytes(*b))?;
+    let track_names = read_strings(r)?;
+    let notes_removed = read_u64(r)?;
+    let warnings = read_strings(r)?;
+
+    let tempo_evs = read_records(r, |b: &[u8; 20]| TempoEvent {
+        time: u64::from_le_bytes(b[0..8].try_into().unwrap()),
+        time_norm: f64::from_le_bytes(b[8..16].try_into().unwrap()),
+        tempo: u32::from_le_bytes(b[16..20].try_into().unwrap())
+    })?;
+    let midi_evs = read_records(r, |b: &[u8; 14]| MIDIEvent::new(
+        f64::from_le_bytes(b[0..8].try_into().unwrap()),
+        u16::from_le_bytes(b[12..14].try_into().unwrap()),
+        b[11], b[8], b[9], b[10]
+    ))?;
+    // the synth and MIDIEvent::kind only know what the parser stores, anything else means the file is broken
+    if midi_evs.iter().any(|e| !(0x80..=0xF0).contains(&e.status) || e.port >= MAX_PORTS) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "event with a status or port the parser never stores"));
+    }
+
+    let meta_count = read_u64(r)?;
+    let mut meta_evs = Vec::new();
+    for _ in 0..meta_count {
+        let mut head = [0u8; 19];
+        r.read_exact(&mut head)?;
+        let meta_name = MetaEventName::from_raw(head[8])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown meta event"))?;
+        let data = read_bytes(r, u64::from_le_bytes(head[11..19].try_into().unwrap()))?;
+        meta_evs.push(MetaEvent {
+            time: f64::from_le_bytes(head[0..8].try_into().unwrap()),
+            meta_name,
+            track: u16::from_le_bytes([head[9], head[10]]),
+            data
+        });
+    }
+
+    // the renderer indexes the notes by key without checking
+    let key_count = read_u64(r)?;
+    if key_count != 256 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("notes for {} keys instead of 256", key_count)));
+    }
+    let mut notes = Vec::new();
+    for _ in 0..key_count {
+        notes.push(read_records(r, |b: &[u8; 16]| Note::from_raw(
+            u64::from_le_bytes(b[0..8].try_into().unwrap()),
+            u32::from_le_bytes(b[8..12].try_into().unwrap()),
+            u32::from_le_bytes(b[12..16].try_into().unwrap())
+        ))?);
+    }
+
+    Ok(CachedMIDI {
+        division,
+        key_range,
+        port_count: port_count[0],
+        note_counts,
+        track_names,
+        notes_removed,
+        warnings,
+        tempo_evs,
+        midi_evs,
+        meta_evs,
+        notes
+    })
+}
+
+fn read_u32<Rd: Read>(r: &mut Rd) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64<Rd: Read>(r: &mut Rd) -> io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+// len bytes, a file that ends before that is cut off
+fn read_bytes<Rd: Read>(r: &mut Rd, len: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    r.by_ref().take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "cache file ends in the middle of an entry"));
+    }
+    Ok(data)
+}
+
+// a count, then every string as its length and its bytes
+fn write_strings<W: Write>(w: &mut W, strings: &[String]) -> io::Result<()> {
+    w.write_all(&(strings.len() as u64).to_le_bytes())?;
+    for s in strings.iter() {
+        w.write_all(&(s.len() as u64).to_le_bytes())?;
+        w.write_all(s.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_strings<Rd: Read>(r: &mut Rd) -> io::Result<Vec<String>> {
+    let count = read_u64(r)?;
+    let mut strings = Vec::new();
+    for _ in 0..count {
+        let len = read_u64(r)?;
+        let text = read_bytes(r, len)?;
+        strings.push(String::from_utf8(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?);
+    }
+    Ok(strings)
+}
+
+// a count followed by fixed size records
+fn write_records<W: Write, T, const N: usize>(w: &mut W, items: &[T], encode: impl Fn(&T) -> [u8; N]) -> io::Result<()> {
+    w.write_all(&(items.len() as u64).to_le_bytes())?;
+    let mut buf = Vec::with_capacity(RECORD_BATCH.min(items.len()) * N);
+    for batch in items.chunks(RECORD_BATCH) {
+        buf.clear();
+        for item in batch {
+            buf.extend_from_slice(&encode(item));
+        }
+        w.write_all(&buf)?;
+    }
+    Ok(())
+}
+
+fn read_records<Rd: Read, T, const N: usize>(r: &mut Rd, decode: impl Fn(&[u8; N]) -> T) -> io::Result<Vec<T>> {
+    let count = read_u64(r)? as usize;
+    // don't trust the count with the allocation, a broken file would just fail further down
+    let mut items = Vec::with_capacity(count.min(RECORD_BATCH * 16));
+    let mut buf = vec![0u8; RECORD_BATCH.min(count) * N];
+    let mut left = count;
+    while left > 0 {
+        let n = left.min(RECORD_BATCH);
+        r.read_exact(&mut buf[..n * N])?;
+        items.extend(buf[..n * N].chunks_exact(N).map(|b| decode(b.try_into().unwrap())));
+        left -= n;
+    }
+    Ok(items)
+}
@@ -0,0 +1,61 @@
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum MidiLoadError {
+    // the file doesn't start with a valid MThd chunk
+    BadHeader(String),
+    // a chunk where an MTrk was expected
+    BadChunk { pos: u64, id: u32 },
+    // a track ran out of bytes in the middle of an event
+    TruncatedTrack { track: Option<usize>, pos: u64 },
+    UnsupportedFormat(u16),
+    // a compressed file or archive that couldn't be unpacked
+    Archive(String),
+    // the user stopped the load, see LoadProgress::cancel
+    Cancelled,
+    Io(io::Error),
+}
+
+impl MidiLoadError {
+    // tags a truncation error coming from a track's reader with the track it belongs to
+    pub fn in_track(self, track_num: usize) -> Self {
+        match self {
+            MidiLoadError::TruncatedTrack { track: None, pos } => MidiLoadError::TruncatedTrack {
+                track: Some(track_num),
+                pos
+            },
+            e => e
+        }
+    }
+}
+
+impl fmt::Display for MidiLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidiLoadError::BadHeader(reason) => write!(f, "invalid MIDI header: {}", reason),
+            MidiLoadError::BadChunk { pos, id } => write!(f, "expected an MTrk chunk at byte {}, found {:?}", pos,
+                String::from_utf8_lossy(&id.to_be_bytes())),
+            MidiLoadError::TruncatedTrack { track: Some(track), pos } => write!(f, "track {} is truncated at byte {}", track, pos),
+            MidiLoadError::TruncatedTrack { track: None, pos } => write!(f, "track data is truncated at byte {}", pos),
+            MidiLoadError::UnsupportedFormat(fmt) => write!(f, "unsupported MIDI format {}", fmt),
+            MidiLoadError::Archive(reason) => write!(f, "couldn't decompress: {}", reason),
+            MidiLoadError::Cancelled => write!(f, "loading was cancelled"),
+            MidiLoadError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MidiLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MidiLoadError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for MidiLoadError {
+    fn from(e: io::Error) -> Self {
+        MidiLoadError::Io(e)
+    }
+}
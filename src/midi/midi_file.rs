@@ -1,101 +1,377 @@
 use std::fs::File;
-use std::io::Seek;
+use std::io::{Read, Seek, SeekFrom};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use memmap2::Mmap;
 use rayon::prelude::*;
 
 use super::byte_reader;
-use crate::midi::midi_track_parser::MIDIEvent;
-use crate::util::iter_ext::{merge_midi_events, merge_notes, merge_tempo_evs};
+use super::midi_archive;
+use super::track_splitter;
+use crate::midi::buffered_byte_reader::BufferedByteReader;
+use crate::midi::mapped_byte_reader::MappedByteReader;
+use crate::midi::track_reader::TrackReader;
+use crate::midi::load_progress::{LoadProgress, LoadStage};
+use crate::midi::midi_error::MidiLoadError;
+use crate::midi::note_filter::NoteFilter;
+use crate::midi::synth_pairing::SynthPairing;
+use crate::midi::midi_track_parser::{MIDIEvent, MetaEvent, MetaEventName};
+use crate::util::iter_ext::merge_sequences;
 
-use super::midi_track_parser::{MIDITrack, TempoEvent, Note};
+use super::midi_track_parser::{MIDITrack, TempoEvent, Note, NotePairing, ParseMode};
+
+const MTHD: u32 = 0x4D546864;
+const MTRK: u32 = 0x4D54726B;
+// how far into the file (or RIFF data chunk) we look for MThd before giving up
+const MTHD_SCAN_LIMIT: u64 = 64 * 1024;
+// how many events a track parses between progress updates and checks for cancelling
+pub const PROGRESS_INTERVAL: u32 = 65536;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimeDivision {
+    // ticks per quarter note
+    PPQ(u16),
+    // fixed ticks per second, tempo events don't apply
+    SMPTE { fps: u8, ticks_per_frame: u8 }
+}
+
+impl TimeDivision {
+    pub fn from_raw(division: u16) -> Result<Self, MidiLoadError> {
+        if division & 0x8000 == 0 {
+            if division == 0 {
+                return Err(MidiLoadError::BadHeader(String::from("division is 0")))
+            }
+            return Ok(TimeDivision::PPQ(division))
+        }
+
+        // upper byte is the negative frame rate, lower byte the ticks per frame
+        let fps = ((division >> 8) as u8 as i8).unsigned_abs();
+        let ticks_per_frame = (division & 0xFF) as u8;
+        match fps {
+            24 | 25 | 29 | 30 if ticks_per_frame > 0 => Ok(TimeDivision::SMPTE { fps, ticks_per_frame }),
+            _ => Err(MidiLoadError::BadHeader(format!("invalid SMPTE division {} fps / {} ticks per frame", fps, ticks_per_frame)))
+        }
+    }
+
+    // what goes back in a header, see midi_writer
+    pub fn to_raw(&self) -> u16 {
+        match *self {
+            TimeDivision::PPQ(ppq) => ppq,
+            TimeDivision::SMPTE { fps, ticks_per_frame } => ((-(fps as i8)) as u8 as u16) << 8 | ticks_per_frame as u16
+        }
+    }
+
+    pub fn is_smpte(&self) -> bool {
+        matches!(self, TimeDivision::SMPTE { .. })
+    }
+
+    // how many ticks pass in a second. only meaningful for SMPTE divisions
+    pub fn ticks_per_second(&self) -> Option<f64> {
+        match *self {
+            TimeDivision::PPQ(_) => None,
+            TimeDivision::SMPTE { fps, ticks_per_frame } => {
+                // 29 means 29.97 drop-frame
+                let real_fps = if fps == 29 { 30000.0 / 1001.0 } else { fps as f64 };
+                Some(real_fps * ticks_per_frame as f64)
+            }
+        }
+    }
+
+    // length of one tick in seconds at the given tempo (microseconds per quarter note)
+    pub fn seconds_per_tick(&self, tempo: u32) -> f64 {
+        match *self {
+            TimeDivision::PPQ(ppq) => (tempo as f64 / ppq as f64) / 1000000.0,
+            TimeDivision::SMPTE { .. } => 1.0 / self.ticks_per_second().unwrap()
+        }
+    }
+}
 
 pub struct TrackPointer {
     pub start: u64,
     pub len: u32
 }
 
-pub struct MIDIFile {
-    pub ppq: u16,
+pub struct MIDIFile<R = File> {
+    pub format: u16,
+    pub division: TimeDivision,
     pub trk_count: u16,
     pub track_locations: Vec<TrackPointer>,
-    pub tracks: Vec<MIDITrack>,
+    pub tracks: Vec<MIDITrack<R>>,
     pub note_counts: Vec<u64>,
+    // from each track's first track name event, empty if it has none
+    pub track_names: Vec<String>,
 
     pub key_range: [u8; 2],
+    pub port_count: u8,
+    // how many notes the note filter took out in get_sequences
+    pub notes_removed: u64,
+    pub parse_mode: ParseMode,
+    pub note_pairing: NotePairing,
+    // problems that were worked around while parsing in lenient mode
+    pub warnings: Vec<String>,
 
-    tempo_evs: Vec<TempoEvent>
+    tempo_evs: Vec<TempoEvent>,
+    // where the midi data stops. this is the end of the "data" chunk for RIFF files
+    data_end: u64,
+    tick_based_parsing: bool,
+    file_stream: Arc<Mutex<R>>,
+    mapped: Option<Arc<Mmap>>,
+    pub progress: Arc<LoadProgress>
 }
 
 impl MIDIFile {
-    pub fn new(path: String, tick_based_parsing: bool) -> Result<Self,()> {
-        let file_stream = Arc::new(Mutex::new(
-            File::open(path).unwrap()
-        ));
+    pub fn new(path: String, tick_based_parsing: bool, parse_mode: ParseMode, note_pairing: NotePairing, progress: Arc<LoadProgress>) -> Result<Self, MidiLoadError> {
+        let mut s = Self::open(&path, None, tick_based_parsing, parse_mode, note_pairing, progress)?;
+        s.parse_tracks()?;
+        Ok(s)
+    }
+
+    // for zips with more than one midi in them
+    pub fn from_zip_entry(path: String, entry: &str, tick_based_parsing: bool, parse_mode: ParseMode, note_pairing: NotePairing, progress: Arc<LoadProgress>) -> Result<Self, MidiLoadError> {
+        let mut s = Self::open(&path, Some(entry), tick_based_parsing, parse_mode, note_pairing, progress)?;
+        s.parse_tracks()?;
+        Ok(s)
+    }
+
+    // only reads the header and finds the tracks, nothing is parsed yet. MIDIStream takes it from here
+    pub fn open(path: &str, zip_entry: Option<&str>, tick_based_parsing: bool, parse_mode: ParseMode, note_pairing: NotePairing, progress: Arc<LoadProgress>) -> Result<Self, MidiLoadError> {
+        let file = midi_archive::open(path, zip_entry, &progress)?;
+
+        // the mapping is only valid as long as nobody truncates the file under us, which is
+        // the same assumption every other midi player makes. fall back to buffered reads if
+        // mapping fails (empty files, special files, etc.)
+        match unsafe { Mmap::map(&file) } {
+            Ok(m) => Self::read_header(file, Some(Arc::new(m)), tick_based_parsing, parse_mode, note_pairing, progress),
+            Err(e) => {
+                println!("couldn't map file, using buffered reads ({})", e);
+                Self::read_header(file, None, tick_based_parsing, parse_mode, note_pairing, progress)
+            }
+        }
+    }
+}
 
+impl<R: Read + Seek + Send> MIDIFile<R> {
+    // parses from any seekable byte source, e.g. a Cursor over bytes that are already in memory.
+    // the player itself always goes through open, this is for the tests and tools embedding the parser
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn from_reader(reader: R, tick_based_parsing: bool, parse_mode: ParseMode, note_pairing: NotePairing, progress: Arc<LoadProgress>) -> Result<Self, MidiLoadError> {
+        let mut s = Self::read_header(reader, None, tick_based_parsing, parse_mode, note_pairing, progress)?;
+        s.parse_tracks()?;
+        Ok(s)
+    }
+
+    fn read_header(reader: R, mapped: Option<Arc<Mmap>>, tick_based_parsing: bool, parse_mode: ParseMode, note_pairing: NotePairing, progress: Arc<LoadProgress>) -> Result<Self, MidiLoadError> {
         let mut s = Self {
-            ppq: 0,
+            format: 0,
+            division: TimeDivision::PPQ(960),
             trk_count: 0,
             track_locations: Vec::new(),
             tracks: Vec::new(),
             note_counts: Vec::new(),
+            track_names: Vec::new(),
 
             tempo_evs: Vec::new(),
-            key_range: [0, 127]
+            key_range: [0, 127],
+            port_count: 1,
+            notes_removed: 0,
+            parse_mode,
+            note_pairing,
+            warnings: Vec::new(),
+            data_end: 0,
+            tick_based_parsing,
+            file_stream: Arc::new(Mutex::new(reader)),
+            mapped,
+            progress
         };
 
         {
+            let file_stream = Arc::clone(&s.file_stream);
             let mut fs = file_stream.lock().unwrap();
-            s.parse_header(&mut fs).unwrap();
-            s.populate_track_locations(&mut fs).unwrap();
+            s.parse_header(&mut fs)?;
+            s.populate_track_locations(&mut fs)?;
         }
 
-        let track_count = s.trk_count;
-        for i in 0usize..(track_count as usize) {
-            s.tracks.push(MIDITrack::new(i, s.ppq, Arc::clone(&file_stream), &s.track_locations[i], tick_based_parsing).unwrap());
-        }
+        s.progress.track_count.store(s.trk_count as usize, Ordering::Relaxed);
+        s.progress.bytes_total.store(s.track_locations.iter().map(|loc| loc.len as u64).sum(), Ordering::Relaxed);
+        Ok(s)
+    }
 
-        println!("----- Parse pass 1 -----");
-        let tempo_evs_seq: Vec<Vec<TempoEvent>>;
+    fn track_reader(&self, i: usize) -> Result<TrackReader<R>, MidiLoadError> {
+        let loc = &self.track_locations[i];
+        Ok(match self.mapped {
+            Some(ref m) => TrackReader::Mapped(MappedByteReader::new(Arc::clone(m), loc.start as usize, loc.len as usize)),
+            None => TrackReader::Buffered(BufferedByteReader::new(Arc::clone(&self.file_stream), loc.start as usize, loc.len as usize, 100000)
+                .map_err(|e| e.in_track(i))?)
+        })
+    }
+
+    // a parser for track i, positioned at the start of the track
+    pub fn new_track(&self, i: usize) -> Result<MIDITrack<R>, MidiLoadError> {
+        MIDITrack::new(i, self.division, self.track_reader(i)?, self.tick_based_parsing, self.parse_mode, self.note_pairing)
+            .map_err(|e| e.in_track(i))
+    }
+
+    pub fn parse_tracks(&mut self) -> Result<(), MidiLoadError> {
+        let track_count = self.trk_count;
+        let progress = Arc::clone(&self.progress);
+        progress.set_stage(LoadStage::Parsing);
+
+        // huge tracks get cut into chunks at the checkpoints a quick pre-scan finds, so a single
+        // track black midi still gets parsed on every core. most tracks are a single chunk
+        let mut chunks: Vec<Vec<MIDITrack<R>>> = self.track_locations.par_iter().enumerate().map(|(i, loc)| {
+            let mut track_chunks = vec![self.new_track(i)?];
+
+            let chunk_count = track_splitter::chunk_count(loc.len as usize);
+            if chunk_count > 1 {
+                let mut note_counts = Vec::new();
+                let checkpoints = track_splitter::find_checkpoints(&mut self.track_reader(i)?, loc.len as usize, chunk_count, &mut note_counts);
+                // every chunk gets stitched onto the first one, so that one makes room for all of them
+                let total: Vec<usize> = (0..256).map(|key| note_counts.iter().map(|counts| counts[key]).sum()).collect();
+                track_chunks[0].reserve_notes(&total);
+                for (cp, counts) in checkpoints.into_iter().zip(note_counts.iter().skip(1)) {
+                    track_chunks.last_mut().unwrap().limit_to(cp.pos);
+                    let mut chunk = self.new_track(i)?;
+                    chunk.resume_at(cp).map_err(|e| e.in_track(i))?;
+                    chunk.reserve_notes(counts);
+                    track_chunks.push(chunk);
+                }
+            }
+            Ok(track_chunks)
+        }).collect::<Result<Vec<_>, MidiLoadError>>()?;
+
+        println!("----- Parsing tracks -----");
+
+        // every track is only read once. times stay in ticks until get_sequences,
+        // since the tempo map isn't complete before all tracks are done
+        chunks.par_iter_mut().flat_map(|track_chunks| track_chunks.par_iter_mut()).map(|chunk| {
+            let mut counted_pos = chunk.rdr.pos();
+            let mut ev_num: u32 = 0;
+            while !chunk.done() {
+                if let Err(e) = chunk.parse_ev() {
+                    chunk.recover(e)?;
+                }
+
+                ev_num = ev_num.wrapping_add(1);
+                if ev_num % PROGRESS_INTERVAL == 0 {
+                    let pos = chunk.rdr.pos();
+                    progress.bytes_read.fetch_add(pos.saturating_sub(counted_pos) as u64, Ordering::Relaxed);
+                    counted_pos = pos;
+                    if progress.is_cancelled() {
+                        return Err(MidiLoadError::Cancelled);
+                    }
+                }
+            }
+            progress.bytes_read.fetch_add(chunk.rdr.pos().saturating_sub(counted_pos) as u64, Ordering::Relaxed);
+            // only the last chunk of a track runs into its end
+            if chunk.ended {
+                progress.tracks_done.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(())
+        }).collect::<Result<(), MidiLoadError>>()?;
 
-        (s.note_counts, tempo_evs_seq) = s.tracks.par_iter_mut().enumerate().map(|(i, track)| {
-            while !track.ended {
-                track.parse_ev().unwrap();
+        self.tracks = chunks.into_par_iter().enumerate().map(|(i, track_chunks)| {
+            let mut track_chunks = track_chunks.into_iter();
+            let mut track = track_chunks.next().unwrap();
+            for chunk in track_chunks {
+                track.append_chunk(chunk);
             }
+            track.close_hanging_notes();
             println!("track {} of {} parsed", i, track_count);
-            track.prep_for_pass_two().unwrap();
-            (track.note_count, std::mem::take(&mut track.tempo_evs))
+            track
         }).collect();
 
-        s.key_range = (
-            s.tracks.iter().map(|track| track.key_range[0]).min().unwrap(),
-            s.tracks.iter().map(|track| track.key_range[1]).max().unwrap()
+        let tempo_evs_seq: Vec<Vec<TempoEvent>>;
+        (self.note_counts, tempo_evs_seq) = self.tracks.iter_mut()
+            .map(|track| (track.note_count, std::mem::take(&mut track.tempo_evs)))
+            .unzip();
+
+        for track in self.tracks.iter_mut() {
+            self.warnings.append(&mut track.warnings);
+        }
+        self.track_names = self.tracks.iter().map(|track| track_name(&track.meta_evs)).collect();
+
+        self.key_range = (
+            self.tracks.iter().map(|track| track.key_range[0]).min().unwrap_or(0),
+            self.tracks.iter().map(|track| track.key_range[1]).max().unwrap_or(127)
         ).into();
+        self.port_count = self.tracks.iter().map(|track| track.port_count).max().unwrap_or(1);
 
-        s.tempo_evs = merge_tempo_evs(tempo_evs_seq);
+        self.tempo_evs = if self.format == 2 {
+            self.offset_sequential_tracks(tempo_evs_seq)
+        } else {
+            merge_sequences(tempo_evs_seq)
+        };
+        normalize_tempo_evs(&mut self.tempo_evs, self.division, 0);
 
-        Ok(s)
+        Ok(())
     }
 
+    // format 2 tracks are played one after another, so every track's timeline gets shifted to start
+    // where the previous one ended. each track also starts off at the default tempo instead of
+    // inheriting whatever the previous track ended on
+    fn offset_sequential_tracks(&mut self, tempo_evs_seq: Vec<Vec<TempoEvent>>) -> Vec<TempoEvent> {
+        let mut tempo_evs = Vec::new();
+        let mut offset: u64 = 0;
+
+        for (track, t_evs) in self.tracks.iter_mut().zip(tempo_evs_seq) {
+            if offset > 0 && !self.division.is_smpte() && t_evs.first().map_or(true, |t| t.time > 0) {
+                tempo_evs.push(TempoEvent {
+                    time: offset,
+                    time_norm: 0.0,
+                    tempo: 500000
+                });
+            }
+            tempo_evs.extend(t_evs.into_iter().map(|t| TempoEvent {
+                time: t.time + offset,
+                time_norm: 0.0,
+                tempo: t.tempo
+            }));
+
+            track.start_tick = offset;
+            offset += track.track_len;
+        }
+
+        tempo_evs
+    }
+
+
     // move from self to Vec<MIDIEvent>
-    pub fn get_sequences(self,
+    pub fn get_sequences(&mut self,
         midi_evs: &mut Vec<MIDIEvent>,
         notes_out: &mut Vec<Vec<Note>>,
-        tempo_evs: &mut Vec<TempoEvent>
-        ) -> () {
-        println!("----- Getting events (Parse pass 2) -----");
-        let (evs, (mut notes, t_evs)): (Vec<Vec<MIDIEvent>>, (Vec<Vec<Vec<Note>>>, Vec<Vec<TempoEvent>>)) = self.tracks.into_par_iter().enumerate().map(|(i, mut track)| {
-            while !track.ended {
-                track.parse_pass_two(&self.tempo_evs).unwrap();
-            }
-            println!("track {} of {} parsed", i, &self.trk_count);
-            (track.midi_evs,
-             (track.notes,
-              track.tempo_evs))
+        tempo_evs: &mut Vec<TempoEvent>,
+        meta_evs: &mut Vec<MetaEvent>,
+        note_filter: NoteFilter
+        ) -> Result<(), MidiLoadError> {
+        println!("----- Getting events -----");
+        let progress = Arc::clone(&self.progress);
+        progress.set_stage(LoadStage::Converting);
+        let tracks = std::mem::take(&mut self.tracks);
+        let notes_removed = AtomicU64::new(0);
+        let converted = tracks.into_par_iter().enumerate().map(|(i, mut track)| {
+            if progress.is_cancelled() {
+                return Err(MidiLoadError::Cancelled);
+            }
+            let removed: usize = track.notes.iter_mut().map(|notes| note_filter.filter_track_notes(notes)).sum();
+            notes_removed.fetch_add(removed as u64, Ordering::Relaxed);
+            track.convert_times(&self.tempo_evs);
+            progress.tracks_done.fetch_add(1, Ordering::Relaxed);
+            println!("track {} of {} converted", i, &self.trk_count);
+            Ok((track.midi_evs, track.notes, track.meta_evs))
+        }).collect::<Result<Vec<_>, MidiLoadError>>()?;
 
-        }).collect();
+        let mut evs: Vec<Vec<MIDIEvent>> = Vec::with_capacity(converted.len());
+        let mut notes: Vec<Vec<Vec<Note>>> = Vec::with_capacity(converted.len());
+        let mut metas: Vec<Vec<MetaEvent>> = Vec::with_capacity(converted.len());
+        for (track_evs, track_notes, track_metas) in converted {
+            evs.push(track_evs);
+            notes.push(track_notes);
+            metas.push(track_metas);
+        }
+        progress.set_stage(LoadStage::Merging);
         println!("merging events...");
-        (*tempo_evs) = merge_tempo_evs(t_evs);
-        println!("merged tempo events");
+        (*tempo_evs) = std::mem::take(&mut self.tempo_evs);
+        (*meta_evs) = merge_sequences(metas);
 
         let notes_per_key: Vec<Vec<Vec<Note>>> = (0..256).map(|_| notes.iter_mut().map(|n| n.pop().unwrap()).collect::<Vec<_>>()).collect::<Vec<_>>();
 
@@ -103,58 +379,504 @@ impl MIDIFile {
         notes_per_key
             .into_par_iter()
             .enumerate()
-            .for_each(|(i, notes_for_key)| {
-                let merged_notes = merge_notes(notes_for_key);
+            .try_for_each(|(i, notes_for_key)| {
+                if progress.is_cancelled() {
+                    return Err(MidiLoadError::Cancelled);
+                }
+                let mut merged_notes = merge_sequences(notes_for_key);
+                notes_removed.fetch_add(note_filter.filter_merged_notes(&mut merged_notes) as u64, Ordering::Relaxed);
                 println!("key {} of {} merged", i, 256);
                 let mut notes_guard = merged_notes_at_keys.lock().unwrap();
                 notes_guard[i] = merged_notes;
-            });
+                progress.keys_merged.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })?;
 
         (*midi_evs, *notes_out) = 
-            (merge_midi_events(evs),
+            (merge_sequences(evs),
             Arc::try_unwrap(merged_notes_at_keys).unwrap().into_inner().unwrap());
+        SynthPairing::new().pair(midi_evs);
+        self.notes_removed = notes_removed.into_inner();
+
+        Ok(())
     }
 
-    fn parse_header(&mut self, stream: &mut File) -> Result<(),&str> {
-        // assuming header length in total is 14
+    fn parse_header(&mut self, stream: &mut R) -> Result<(), MidiLoadError> {
+        self.data_end = stream.seek(SeekFrom::End(0))?;
+        stream.seek(SeekFrom::Start(0))?;
+        self.unwrap_riff(stream)?;
+        self.find_mthd(stream)?;
+
         // MThd header
-        let mthd: u32 = byte_reader::read_u32(stream).unwrap();
-        assert_eq!(mthd, 0x4D546864);
+        let mthd: u32 = byte_reader::read_u32(stream)?;
+        if mthd != MTHD {
+            return Err(MidiLoadError::BadHeader(String::from("missing MThd chunk")))
+        }
 
         // length
-        let h_len: u32 = byte_reader::read_u32(stream).unwrap();
-        assert_eq!(h_len, 6);
+        let h_len: u32 = byte_reader::read_u32(stream)?;
+        if h_len < 6 {
+            return Err(MidiLoadError::BadHeader(format!("header length is {}, expected at least 6", h_len)))
+        }
         // format lol
-        let m_fmt: u16 = byte_reader::read_u16(stream).unwrap();
-        if m_fmt == 2 {
-            return Err("please stop using format 2")
+        let m_fmt: u16 = byte_reader::read_u16(stream)?;
+        if m_fmt > 2 {
+            return Err(MidiLoadError::UnsupportedFormat(m_fmt))
         }
         // track count (i think)
-        let m_trk_count: u16 = byte_reader::read_u16(stream).unwrap();
-        let m_ppq: u16 = byte_reader::read_u16(stream).unwrap();
+        let m_trk_count: u16 = byte_reader::read_u16(stream)?;
+        let m_division: u16 = byte_reader::read_u16(stream)?;
         
+        self.format = m_fmt;
         self.trk_count = m_trk_count;
-        self.ppq = m_ppq;
+        self.division = TimeDivision::from_raw(m_division)?;
+
+        // newer revisions of the spec are allowed to add fields to the header
+        if h_len > 6 {
+            stream.seek_relative(h_len as i64 - 6)?;
+        }
+
+        Ok(())
+    }
+
+    // .rmi files wrap the whole smf in a RIFF RMID container. if that's the case, move the stream
+    // to the start of the "data" chunk and limit parsing to it
+    fn unwrap_riff(&mut self, stream: &mut R) -> Result<(), MidiLoadError> {
+        let mut riff = [0u8; 12];
+        if self.data_end < 12 {
+            return Ok(())
+        }
+        stream.read_exact(&mut riff)?;
+        if &riff[0..4] != b"RIFF" || &riff[8..12] != b"RMID" {
+            stream.seek(SeekFrom::Start(0))?;
+            return Ok(())
+        }
+
+        let mut pos: u64 = 12;
+        while pos + 8 <= self.data_end {
+            let id: u32 = byte_reader::read_u32(stream)?;
+            let len: u32 = byte_reader::read_u32_le(stream)?;
+            pos += 8;
+            if &id.to_be_bytes() == b"data" {
+                self.data_end = self.data_end.min(pos + len as u64);
+                return Ok(())
+            }
+            // chunks are padded to an even length
+            pos += len as u64 + (len & 1) as u64;
+            stream.seek(SeekFrom::Start(pos))?;
+        }
+
+        Err(MidiLoadError::BadHeader(String::from("RIFF file has no MIDI data chunk")))
+    }
+
+    // some files have junk in front of the header (macbinary headers, leftovers from rippers, etc.),
+    // so look a bit further for MThd
+    fn find_mthd(&mut self, stream: &mut R) -> Result<(), MidiLoadError> {
+        let start = stream.stream_position()?;
+        let scan_len = MTHD_SCAN_LIMIT.min(self.data_end.saturating_sub(start));
+        let mut buf = Vec::new();
+        stream.by_ref().take(scan_len).read_to_end(&mut buf)?;
+
+        let offset = buf.windows(4)
+            .position(|w| w == MTHD.to_be_bytes())
+            .unwrap_or(0) as u64;
+        if offset > 0 {
+            println!("skipped {} bytes before the MIDI header", offset);
+        }
+        stream.seek(SeekFrom::Start(start + offset))?;
 
         Ok(())
     }
 
-    fn populate_track_locations(&mut self, stream: &mut File) -> Result<(), &str> {
-        for _ in 0..self.trk_count {
-            let mtrk: u32 = byte_reader::read_u32(stream).unwrap();
-            assert_eq!(mtrk, 0x4D54726B);
-            
-            let t_len: u32 = byte_reader::read_u32(stream).unwrap();
-            let pos: u64 = stream.stream_position().unwrap();
+    fn populate_track_locations(&mut self, stream: &mut R) -> Result<(), MidiLoadError> {
+        let data_end = self.data_end;
+        let mut i: u16 = 0;
+        while i < self.trk_count {
+            let chunk_pos: u64 = stream.stream_position()?;
+            if self.parse_mode == ParseMode::Lenient && chunk_pos + 8 > data_end {
+                self.warnings.push(format!("header says there are {} tracks, but the file only has {}", self.trk_count, i));
+                self.trk_count = i;
+                break;
+            }
+
+            let chunk_id: u32 = byte_reader::read_u32(stream)?;
+            let mut t_len: u32 = byte_reader::read_u32(stream)?;
+            let pos: u64 = stream.stream_position()?;
+
+            if chunk_id != MTRK {
+                // vendor chunks (XFIH, XFKM, ...) are skipped, anything that doesn't even look like a chunk id isn't
+                if !chunk_id.to_be_bytes().iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+                    return Err(MidiLoadError::BadChunk { pos: chunk_pos, id: chunk_id })
+                }
+                println!("skipping {:?} chunk at byte {}", String::from_utf8_lossy(&chunk_id.to_be_bytes()), chunk_pos);
+                stream.seek(SeekFrom::Start(pos + t_len as u64))?;
+                continue;
+            }
+
+            if pos + t_len as u64 > data_end {
+                if self.parse_mode == ParseMode::Strict {
+                    return Err(MidiLoadError::TruncatedTrack { track: Some(i as usize), pos: data_end })
+                }
+                self.warnings.push(format!("track {} claims {} bytes but only {} are left in the file", i, t_len, data_end - pos));
+                t_len = (data_end - pos) as u32;
+            }
 
-            stream.seek_relative(t_len as i64).unwrap();
+            stream.seek_relative(t_len as i64)?;
 
             self.track_locations.push(TrackPointer {
                 start: pos,
                 len: t_len
             });
+            i += 1;
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+// fills in the time (in seconds) each tempo event from first on happens at. the ones before
+// first have to be filled in already
+pub fn normalize_tempo_evs(tempo_evs: &mut [TempoEvent], division: TimeDivision, first: usize) -> () {
+    let (mut time_norm, mut last_tick, mut tempo_multi) = match first.checked_sub(1) {
+        Some(i) => (tempo_evs[i].time_norm, tempo_evs[i].time, division.seconds_per_tick(tempo_evs[i].tempo)),
+        None => (0.0, 0, division.seconds_per_tick(500000))
+    };
+
+    for t in tempo_evs[first..].iter_mut() {
+        time_norm += (t.time - last_tick) as f64 * tempo_multi;
+        t.time_norm = time_norm;
+        last_tick = t.time;
+        tempo_multi = division.seconds_per_tick(t.tempo);
+    }
+}
+
+pub fn track_name(meta_evs: &[MetaEvent]) -> String {
+    meta_evs.iter()
+        .find(|ev| ev.meta_name == MetaEventName::TrackName)
+        .map_or(String::new(), |ev| ev.text())
+}
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::midi::midi_track_parser::MIDIEventType;
+
+    const PPQ_96: u16 = 96;
+
+    struct Parsed {
+        midi_evs: Vec<MIDIEvent>,
+        notes: Vec<Vec<Note>>,
+        tempo_evs: Vec<TempoEvent>,
+        warnings: Vec<String>
+    }
+
+    impl Parsed {
+        // (start, end) of every note on key, in seconds. only for midis parsed in time mode
+        fn note_secs(&self, key: usize) -> Vec<(f64, f64)> {
+            self.notes[255 - key].iter().map(|n| (n.start as f64 / 1000000.0, n.end() as f64 / 1000000.0)).collect()
+        }
+
+        fn note_ticks(&self, key: usize) -> Vec<(u64, u64)> {
+            self.notes[255 - key].iter().map(|n| (n.start, n.end())).collect()
+        }
+
+        // (time, status, key) of what the synth gets
+        fn evs(&self) -> Vec<(f64, u8, u8)> {
+            self.midi_evs.iter().map(|e| (e.time, e.status, e.data[0])).collect()
+        }
+    }
+
+    fn parse(bytes: Vec<u8>, tick_based: bool, mode: ParseMode) -> Result<Parsed, MidiLoadError> {
+        parse_paired(bytes, tick_based, mode, NotePairing::Lifo)
+    }
+
+    fn parse_paired(bytes: Vec<u8>, tick_based: bool, mode: ParseMode, pairing: NotePairing) -> Result<Parsed, MidiLoadError> {
+        let mut mid = MIDIFile::from_reader(Cursor::new(bytes), tick_based, mode, pairing, Arc::new(LoadProgress::new()))?;
+        let mut parsed = Parsed {
+            midi_evs: Vec::new(),
+            notes: Vec::new(),
+            tempo_evs: Vec::new(),
+            warnings: Vec::new()
+        };
+        let mut meta_evs = Vec::new();
+        mid.get_sequences(&mut parsed.midi_evs, &mut parsed.notes, &mut parsed.tempo_evs, &mut meta_evs, NoteFilter::default())?;
+        parsed.warnings = std::mem::take(&mut mid.warnings);
+        Ok(parsed)
+    }
+
+    // events are (delta, bytes), deltas up to 16383 ticks. nothing gets added at the end
+    fn events(evs: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (delta, ev) in evs {
+            assert!(*delta < 0x4000);
+            if *delta >= 0x80 {
+                data.push(0x80 | (delta >> 7) as u8);
+            }
+            data.push((delta & 0x7F) as u8);
+            data.extend_from_slice(ev);
+        }
+        data
+    }
+
+    // an MTrk chunk that says it's len bytes long, whether or not that's true
+    fn chunk(data: &[u8], len: u32) -> Vec<u8> {
+        let mut chunk = b"MTrk".to_vec();
+        chunk.extend_from_slice(&len.to_be_bytes());
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    fn track(evs: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut data = events(evs);
+        data.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+        chunk(&data, data.len() as u32)
+    }
+
+    fn smf(format: u16, division: u16, tracks: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = b"MThd".to_vec();
+        buf.extend_from_slice(&6u32.to_be_bytes());
+        buf.extend_from_slice(&format.to_be_bytes());
+        buf.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&division.to_be_bytes());
+        for t in tracks {
+            buf.extend_from_slice(t);
+        }
+        buf
+    }
+
+    fn assert_secs(got: &[(f64, f64)], want: &[(f64, f64)]) {
+        assert_eq!(got.len(), want.len(), "{:?} != {:?}", got, want);
+        for (g, w) in got.iter().zip(want) {
+            // notes are whole microseconds
+            assert!((g.0 - w.0).abs() < 1e-6 && (g.1 - w.1).abs() < 1e-6, "{:?} != {:?}", got, want);
+        }
+    }
+
+    fn ev_at(evs: &[(f64, u8, u8)], status: u8, key: u8) -> f64 {
+        evs.iter().find(|e| e.1 == status && e.2 == key).unwrap().0
+    }
+
+    // the tempo changes halfway through the first track, and then again in the second one.
+    // the second track's events before that change still go by the first track's tempos
+    fn tempo_changes() -> Vec<u8> {
+        let first = track(&[
+            (0, &[0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20]),
+            (0, &[0x90, 60, 100]),
+            (96, &[0x80, 60, 0]),
+            (48, &[0x90, 62, 90]),
+            (48, &[0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90]),
+            (48, &[0x80, 62, 0])
+        ]);
+        let second = track(&[
+            (240, &[0x91, 64, 80]),
+            (48, &[0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40]),
+            (48, &[0xB1, 7, 100]),
+            (48, &[0x81, 64, 0])
+        ]);
+        smf(1, PPQ_96, &[first, second])
+    }
+
+    #[test]
+    fn tempo_changes_within_and_across_tracks() {
+        let parsed = parse(tempo_changes(), false, ParseMode::Strict).unwrap();
+
+        // 96 ticks at 500000 is half a second, at 250000 a quarter and at 1000000 a whole second
+        assert_secs(&parsed.note_secs(60), &[(0.0, 0.5)]);
+        assert_secs(&parsed.note_secs(62), &[(0.75, 1.125)]);
+        assert_secs(&parsed.note_secs(64), &[(1.125, 2.25)]);
+
+        let tempos: Vec<(u64, f64, u32)> = parsed.tempo_evs.iter().map(|t| (t.time, t.time_norm, t.tempo)).collect();
+        assert_eq!(tempos, vec![(0, 0.0, 500000), (192, 1.0, 250000), (288, 1.25, 1000000)]);
+
+        let evs = parsed.evs();
+        assert!((ev_at(&evs, 0x80, 60) - 0.5).abs() < 1e-9);
+        assert!((ev_at(&evs, 0x90, 62) - 0.75).abs() < 1e-9);
+        assert!((ev_at(&evs, 0x91, 64) - 1.125).abs() < 1e-9);
+        assert!((ev_at(&evs, 0xB1, 7) - 1.75).abs() < 1e-9);
+        assert!((ev_at(&evs, 0x81, 64) - 2.25).abs() < 1e-9);
+        assert!(evs.windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+
+    #[test]
+    fn tick_based_notes_stay_in_ticks() {
+        let parsed = parse(tempo_changes(), true, ParseMode::Strict).unwrap();
+
+        assert_eq!(parsed.note_ticks(60), vec![(0, 96)]);
+        assert_eq!(parsed.note_ticks(62), vec![(144, 240)]);
+        assert_eq!(parsed.note_ticks(64), vec![(240, 384)]);
+        // the synth still gets seconds
+        assert!((ev_at(&parsed.evs(), 0xB1, 7) - 1.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn format_2_tracks_play_one_after_another() {
+        let first = track(&[
+            (0, &[0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90]),
+            (0, &[0x90, 60, 100]),
+            (96, &[0x80, 60, 0])
+        ]);
+        // starts where the first one ends, back at the default tempo
+        let second = track(&[
+            (0, &[0x90, 62, 100]),
+            (96, &[0x80, 62, 0])
+        ]);
+        let parsed = parse(smf(2, PPQ_96, &[first, second]), false, ParseMode::Strict).unwrap();
+
+        assert_secs(&parsed.note_secs(60), &[(0.0, 0.25)]);
+        assert_secs(&parsed.note_secs(62), &[(0.25, 0.75)]);
+        let evs = parsed.evs();
+        assert!((ev_at(&evs, 0x90, 62) - 0.25).abs() < 1e-9);
+        assert!((ev_at(&evs, 0x80, 62) - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn lenient_mode_ends_a_truncated_last_track() {
+        let first = track(&[
+            (0, &[0x90, 60, 100]),
+            (96, &[0x80, 60, 0])
+        ]);
+        // cut off in the middle of the note off, and the chunk claims more than the file has
+        let data = events(&[
+            (0, &[0x90, 62, 100]),
+            (48, &[0x80, 62])
+        ]);
+        let second = chunk(&data, data.len() as u32 + 10);
+        let bytes = smf(1, PPQ_96, &[first, second]);
+
+        assert!(matches!(parse(bytes.clone(), true, ParseMode::Strict), Err(MidiLoadError::TruncatedTrack { track: Some(1), .. })));
+
+        let parsed = parse(bytes, true, ParseMode::Lenient).unwrap();
+        assert_eq!(parsed.note_ticks(60), vec![(0, 96)]);
+        // closed at the last tick the track got to
+        assert_eq!(parsed.note_ticks(62), vec![(0, 48)]);
+        assert!(parsed.warnings.iter().any(|w| w.contains("track 1 is truncated")), "{:?}", parsed.warnings);
+        assert!(parsed.warnings.iter().any(|w| w.contains("closed 1 hanging notes in track 1")), "{:?}", parsed.warnings);
+        // the synth lets go of it too
+        assert_eq!(parsed.midi_evs.iter().filter(|e| e.kind() == MIDIEventType::NoteOff && e.data[0] == 62).count(), 1);
+    }
+
+    #[test]
+    fn lenient_mode_ends_a_track_without_end_of_track() {
+        let data = events(&[
+            (0, &[0x90, 60, 100]),
+            (48, &[0xB0, 7, 100])
+        ]);
+        let bytes = smf(0, PPQ_96, &[chunk(&data, data.len() as u32)]);
+
+        assert!(matches!(parse(bytes.clone(), true, ParseMode::Strict), Err(MidiLoadError::TruncatedTrack { track: Some(0), .. })));
+
+        let parsed = parse(bytes, true, ParseMode::Lenient).unwrap();
+        assert_eq!(parsed.note_ticks(60), vec![(0, 48)]);
+        assert!(parsed.warnings.iter().any(|w| w.contains("track 0 has no end-of-track event")), "{:?}", parsed.warnings);
+    }
+
+    // stacked notes on a couple of keys and channels, some on a second port, ending with plenty
+    // of them still open
+    fn stacked_notes() -> Vec<u8> {
+        let mut seed: u32 = 12345;
+        let mut rand = |n: u32| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) % n
+        };
+        let mut evs: Vec<(u16, Vec<u8>)> = Vec::new();
+        for _ in 0..3000 {
+            let delta = [0, 0, 1, 3, 10][rand(5) as usize];
+            let (key, ch) = (60 + rand(2) as u8, rand(2) as u8);
+            let ev = match rand(10) {
+                0..=3 => vec![0x90 | ch, key, 1 + rand(127) as u8],
+                4..=6 => vec![0x80 | ch, key, rand(128) as u8],
+                7 => vec![0x90 | ch, key, 0],
+                8 => vec![0xFF, 0x21, 0x01, rand(2) as u8],
+                _ => vec![0xB0 | ch, 7, rand(128) as u8]
+            };
+            evs.push((delta, ev));
+        }
+        let evs: Vec<(u16, &[u8])> = evs.iter().map(|(delta, ev)| (*delta, ev.as_slice())).collect();
+        smf(0, PPQ_96, &[track(&evs)])
+    }
+
+    fn parse_track(track: &mut MIDITrack<Cursor<Vec<u8>>>) {
+        while !track.done() {
+            if let Err(e) = track.parse_ev() {
+                track.recover(e).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_stitch_together_like_one_track() {
+        for pairing in [NotePairing::Lifo, NotePairing::Fifo, NotePairing::TruncatePrevious] {
+            let mid = MIDIFile::read_header(Cursor::new(stacked_notes()), None, true, ParseMode::Lenient, pairing, Arc::new(LoadProgress::new())).unwrap();
+
+            let mut whole = mid.new_track(0).unwrap();
+            parse_track(&mut whole);
+            whole.close_hanging_notes();
+
+            // the same way parse_tracks does it
+            let mut note_counts = Vec::new();
+            let checkpoints = track_splitter::find_checkpoints(&mut mid.track_reader(0).unwrap(), mid.track_locations[0].len as usize, 6, &mut note_counts);
+            assert_eq!(checkpoints.len(), 5);
+            let mut chunks = vec![mid.new_track(0).unwrap()];
+            for cp in checkpoints {
+                chunks.last_mut().unwrap().limit_to(cp.pos);
+                let mut chunk = mid.new_track(0).unwrap();
+                chunk.resume_at(cp).unwrap();
+                chunks.push(chunk);
+            }
+            for chunk in chunks.iter_mut() {
+                parse_track(chunk);
+            }
+            let mut chunks = chunks.into_iter();
+            let mut stitched = chunks.next().unwrap();
+            for chunk in chunks {
+                stitched.append_chunk(chunk);
+            }
+            stitched.close_hanging_notes();
+
+            let counted: usize = note_counts.iter().flatten().sum();
+            assert_eq!(counted as u64, whole.note_count, "{:?}", pairing);
+            assert_eq!(stitched.notes, whole.notes, "{:?}", pairing);
+            assert_eq!(stitched.midi_evs, whole.midi_evs, "{:?}", pairing);
+        }
+    }
+
+    // the synth lets go of the oldest voice whatever the pairing is, but it should still have
+    // as many playing on a key as there are notes drawn there, without getting any note ons added
+    #[test]
+    fn synth_plays_as_many_voices_as_notes_are_drawn() {
+        for pairing in [NotePairing::Lifo, NotePairing::Fifo, NotePairing::TruncatePrevious] {
+            let parsed = parse_paired(stacked_notes(), true, ParseMode::Lenient, pairing).unwrap();
+
+            let note_count: usize = parsed.notes.iter().map(|n| n.len()).sum();
+            let note_ons = parsed.midi_evs.iter().filter(|e| e.kind() == MIDIEventType::NoteOn).count();
+            assert_eq!(note_ons, note_count, "{:?}", pairing);
+
+            let mut voices: HashMap<(u8, u8), u32> = HashMap::new();
+            let mut i = 0;
+            while i < parsed.midi_evs.len() {
+                let time = parsed.midi_evs[i].time;
+                while i < parsed.midi_evs.len() && parsed.midi_evs[i].time == time {
+                    let ev = &parsed.midi_evs[i];
+                    let playing = voices.entry((ev.full_channel(), ev.data[0])).or_insert(0);
+                    match ev.kind() {
+                        MIDIEventType::NoteOn => *playing += 1,
+                        MIDIEventType::NoteOff => *playing = playing.saturating_sub(1),
+                        _ => {}
+                    }
+                    i += 1;
+                }
+
+                // events are in seconds either way, at the default 120 bpm that's 192 ticks each
+                let tick = (time * 2.0 * PPQ_96 as f64).round() as u64;
+                for (&(channel, key), &playing) in voices.iter() {
+                    let drawn = parsed.notes[255 - key as usize].iter()
+                        .filter(|n| n.channel() == channel && n.start <= tick && n.end() > tick)
+                        .count();
+                    assert_eq!(playing as usize, drawn, "{:?} key {} channel {} at tick {}", pairing, key, channel, tick);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use rayon::prelude::*;
+
+use crate::midi::load_progress::{LoadProgress, LoadStage};
+use crate::midi::midi_error::MidiLoadError;
+use crate::midi::midi_file::{self, MIDIFile, TimeDivision};
+use crate::midi::midi_track_parser::{self, MIDIEvent, MIDITrack, MetaEvent, Note, NotePairing, ParseMode, TempoEvent};
+use crate::midi::note_filter::NoteFilter;
+use crate::midi::synth_pairing::SynthPairing;
+use crate::util::iter_ext::merge_sequences;
+
+// one piece of a midi that's being streamed in. everything in it comes before anything in the next one
+pub struct StreamWindow {
+    // how far the midi is loaded, in seconds. on the last window it's where the midi ends
+    pub until: f64,
+    pub midi_evs: Vec<MIDIEvent>,
+    pub notes: Vec<Vec<Note>>,
+    // only the tempo events that are new in this window
+    pub tempo_evs: Vec<TempoEvent>,
+    pub meta_evs: Vec<MetaEvent>,
+    pub division: TimeDivision,
+    // these cover everything loaded so far
+    pub key_range: [u8; 2],
+    pub port_count: u8,
+    pub note_count: u64,
+    pub notes_removed: u64,
+    pub track_names: Vec<String>,
+    pub warnings: Vec<String>,
+    pub finished: bool
+}
+
+// loads a midi a few bars at a time so playback can start before the whole file is parsed.
+// every track gets parsed up to the same tick, then whatever can't change anymore is converted,
+// merged and handed out. huge tracks aren't split here, see MIDIFile::parse_tracks for that
+pub struct MIDIStream<R = File> {
+    mid: MIDIFile<R>,
+    tracks: Vec<MIDITrack<R>>,
+    // the tempo map so far, with time_norm filled in
+    tempo_evs: Vec<TempoEvent>,
+    tick_based: bool,
+    note_filter: NoteFilter,
+    synth_pairing: SynthPairing,
+    notes_removed: u64,
+    // a track's name shows up whenever its first track name event gets parsed
+    track_names: Vec<String>,
+    window_len: u64,
+    // the earliest tick any track continues at. everything before it is parsed
+    next_tick: u64,
+    last_time: f64,
+    finished: bool
+}
+
+impl MIDIStream {
+    pub fn open(path: &str, zip_entry: Option<&str>, tick_based: bool, parse_mode: ParseMode, note_pairing: NotePairing, note_filter: NoteFilter, progress: Arc<LoadProgress>) -> Result<Self, MidiLoadError> {
+        Self::new(MIDIFile::open(path, zip_entry, tick_based, parse_mode, note_pairing, progress)?, tick_based, note_filter)
+    }
+}
+
+impl<R: Read + Seek + Send> MIDIStream<R> {
+    pub fn new(mid: MIDIFile<R>, tick_based: bool, note_filter: NoteFilter) -> Result<Self, MidiLoadError> {
+        // format 2 tracks play one after another, so they all get loaded at once in next_window
+        let tracks = if mid.format == 2 {
+            Vec::new()
+        } else {
+            (0..mid.trk_count as usize).map(|i| mid.new_track(i)).collect::<Result<Vec<_>, MidiLoadError>>()?
+        };
+
+        // four bars of 4/4, or 8 seconds of SMPTE ticks
+        let window_len = match mid.division {
+            TimeDivision::PPQ(ppq) => ppq as u64 * 16,
+            TimeDivision::SMPTE { .. } => (mid.division.ticks_per_second().unwrap() * 8.0) as u64
+        };
+
+        mid.progress.set_stage(LoadStage::Parsing);
+        Ok(Self {
+            track_names: vec![String::new(); tracks.len()],
+            synth_pairing: SynthPairing::new(),
+            mid,
+            tracks,
+            tempo_evs: Vec::new(),
+            tick_based,
+            note_filter,
+            notes_removed: 0,
+            window_len,
+            next_tick: 0,
+            last_time: 0.0,
+            finished: false
+        })
+    }
+
+    // None once the whole midi has been handed out
+    pub fn next_window(&mut self) -> Result<Option<StreamWindow>, MidiLoadError> {
+        if self.finished {
+            return Ok(None);
+        }
+        let progress = Arc::clone(&self.mid.progress);
+        if progress.is_cancelled() {
+            return Err(MidiLoadError::Cancelled);
+        }
+        if self.mid.format == 2 {
+            return self.load_sequential().map(Some);
+        }
+
+        // skipping straight to the next event keeps long silences from turning into lots of empty windows
+        let until = self.next_tick.saturating_add(self.window_len);
+        let next_ticks = self.tracks.par_iter_mut().map(|track| {
+            let next = track.parse_until(until)?;
+            // the track ended in this window, so its hanging notes end after everything handed out before
+            if next.is_none() {
+                track.close_hanging_notes();
+            }
+            Ok(next)
+        }).collect::<Result<Vec<_>, MidiLoadError>>()?;
+
+        let next_tick = next_ticks.into_iter().flatten().min();
+        self.finished = next_tick.is_none();
+        self.next_tick = next_tick.unwrap_or(u64::MAX);
+
+        // every tempo event from here on comes later than the ones already in the map
+        let division = self.mid.division;
+        let first_new = self.tempo_evs.len();
+        self.tempo_evs.extend(merge_sequences(self.tracks.iter_mut().map(|track| std::mem::take(&mut track.tempo_evs)).collect()));
+        midi_file::normalize_tempo_evs(&mut self.tempo_evs, division, first_new);
+
+        // notes have to come out sorted by start on every key, so nothing on a key can be handed out
+        // past the earliest note there that's still waiting for its note off, in any track
+        let bounds: Vec<u64> = if self.finished {
+            vec![u64::MAX; 256]
+        } else {
+            self.tracks.par_iter()
+                .map(|track| (0..256).map(|key| track.first_open_start(key).unwrap_or(u64::MAX)).collect::<Vec<_>>())
+                .reduce(|| vec![u64::MAX; 256], |a, b| a.into_iter().zip(b).map(|(a, b)| a.min(b)).collect())
+        };
+
+        let tempo_evs = &self.tempo_evs;
+        let tick_based = self.tick_based;
+        let note_filter = self.note_filter;
+        let converted = self.tracks.par_iter_mut().map(|track| {
+            let mut evs = std::mem::take(&mut track.midi_evs);
+            midi_track_parser::convert_event_times(&mut evs, tempo_evs, division, 0);
+            let mut metas = std::mem::take(&mut track.meta_evs);
+            midi_track_parser::convert_meta_times(&mut metas, tempo_evs, division, 0);
+
+            let mut removed = 0;
+            let notes = (0..256).map(|key| {
+                let count = track.notes[key].partition_point(|n| n.start < bounds[key]);
+                let mut notes = track.take_notes(key, count);
+                removed += note_filter.filter_track_notes(&mut notes);
+                midi_track_parser::convert_note_times(&mut notes, tempo_evs, division, 0, tick_based);
+                notes
+            }).collect::<Vec<_>>();
+            (evs, notes, metas, removed)
+        }).collect::<Vec<_>>();
+
+        let mut evs: Vec<Vec<MIDIEvent>> = Vec::with_capacity(converted.len());
+        let mut notes: Vec<Vec<Vec<Note>>> = Vec::with_capacity(converted.len());
+        let mut metas: Vec<Vec<MetaEvent>> = Vec::with_capacity(converted.len());
+        for (name, (track_evs, track_notes, track_metas, removed)) in self.track_names.iter_mut().zip(converted) {
+            if name.is_empty() {
+                *name = midi_file::track_name(&track_metas);
+            }
+            evs.push(track_evs);
+            notes.push(track_notes);
+            metas.push(track_metas);
+            self.notes_removed += removed as u64;
+        }
+
+        // same key order get_sequences hands out. notes that start together always end up
+        // in the same window, so duplicates can't be split across two
+        let notes_per_key: Vec<Vec<Vec<Note>>> = (0..256).map(|_| notes.iter_mut().map(|n| n.pop().unwrap()).collect::<Vec<_>>()).collect::<Vec<_>>();
+        let (notes, removed): (Vec<Vec<Note>>, Vec<usize>) = notes_per_key.into_par_iter().map(|seqs| {
+            let mut merged = merge_sequences(seqs);
+            let removed = note_filter.filter_merged_notes(&mut merged);
+            (merged, removed)
+        }).unzip();
+        self.notes_removed += removed.iter().sum::<usize>() as u64;
+        let mut midi_evs = merge_sequences(evs);
+        self.synth_pairing.pair(&mut midi_evs);
+        let meta_evs = merge_sequences(metas);
+
+        if let Some(ev) = midi_evs.last() {
+            self.last_time = ev.time;
+        }
+        let until = if self.finished {
+            self.last_time
+        } else {
+            midi_track_parser::ticks_to_seconds(&self.tempo_evs, division, self.next_tick)
+        };
+
+        progress.bytes_read.store(self.tracks.iter().zip(&self.mid.track_locations)
+            .map(|(track, loc)| (track.rdr.pos() as u64).saturating_sub(loc.start))
+            .sum(), Ordering::Relaxed);
+        progress.tracks_done.store(self.tracks.iter().filter(|track| track.ended).count(), Ordering::Relaxed);
+
+        let mut warnings = std::mem::take(&mut self.mid.warnings);
+        for track in self.tracks.iter_mut() {
+            warnings.append(&mut track.warnings);
+        }
+
+        Ok(Some(StreamWindow {
+            until,
+            midi_evs,
+            notes,
+            tempo_evs: self.tempo_evs[first_new..].to_vec(),
+            meta_evs,
+            division,
+            key_range: [
+                self.tracks.iter().map(|track| track.key_range[0]).min().unwrap_or(0),
+                self.tracks.iter().map(|track| track.key_range[1]).max().unwrap_or(127)
+            ],
+            port_count: self.tracks.iter().map(|track| track.port_count).max().unwrap_or(1),
+            note_count: self.tracks.iter().map(|track| track.note_count).sum(),
+            notes_removed: self.notes_removed,
+            track_names: self.track_names.clone(),
+            warnings,
+            finished: self.finished
+        }))
+    }
+
+    // format 2 timelines depend on how long every track before them is, so there's nothing to stream
+    fn load_sequential(&mut self) -> Result<StreamWindow, MidiLoadError> {
+        self.mid.parse_tracks()?;
+
+        let mut midi_evs: Vec<MIDIEvent> = Vec::new();
+        let mut notes: Vec<Vec<Note>> = Vec::new();
+        let mut tempo_evs: Vec<TempoEvent> = Vec::new();
+        let mut meta_evs: Vec<MetaEvent> = Vec::new();
+        self.mid.get_sequences(&mut midi_evs, &mut notes, &mut tempo_evs, &mut meta_evs, self.note_filter)?;
+        self.finished = true;
+
+        Ok(StreamWindow {
+            until: midi_evs.last().map_or(0.0, |ev| ev.time),
+            midi_evs,
+            notes,
+            tempo_evs,
+            meta_evs,
+            division: self.mid.division,
+            key_range: self.mid.key_range,
+            port_count: self.mid.port_count,
+            note_count: self.mid.note_counts.iter().sum(),
+            notes_removed: self.mid.notes_removed,
+            track_names: std::mem::take(&mut self.mid.track_names),
+            warnings: std::mem::take(&mut self.mid.warnings),
+            finished: true
+        })
+    }
+}
@@ -1,292 +1,699 @@
 use std::fs::File;
-use std::sync::{Arc, Mutex};
+use std::io::{Read, Seek};
 
-use crate::midi::buffered_byte_reader::BufferedByteReader;
-use crate::midi::midi_file::TrackPointer;
+use crate::midi::midi_error::MidiLoadError;
+use crate::midi::midi_file::TimeDivision;
+use crate::midi::midi_validator::{FindingKind, Findings};
+use crate::midi::track_reader::TrackReader;
+use crate::midi::track_splitter::Checkpoint;
 
+// ports past this share the last one, the port and channel have to fit in a note's channel byte
+pub const MAX_PORTS: u8 = 16;
+
+#[derive(Clone, Copy)]
 pub struct TempoEvent {
     pub time: u64, // absolute time
-    pub time_norm: f32,
+    pub time_norm: f64,
     pub tempo: u32
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum MetaEventName {
+    Text=0x01,
+    Copyright=0x02,
+    TrackName=0x03,
+    InstrumentName=0x04,
+    Lyric=0x05,
     Marker=0x06,
+    CuePoint=0x07,
     TimeSignature=0x58,
-    KeySignature=0x59
+    KeySignature=0x59,
+    // not meta events, but the raw bytes of sysex messages are kept the same way.
+    // data is everything after the status byte
+    SysEx=0xF0,
+    SysExEscape=0xF7
+}
+
+impl MetaEventName {
+    // the meta events that get kept, everything else is skipped over
+    pub fn from_raw(kind: u8) -> Option<Self> {
+        match kind {
+            0x01 => Some(MetaEventName::Text),
+            0x02 => Some(MetaEventName::Copyright),
+            0x03 => Some(MetaEventName::TrackName),
+            0x04 => Some(MetaEventName::InstrumentName),
+            0x05 => Some(MetaEventName::Lyric),
+            0x06 => Some(MetaEventName::Marker),
+            0x07 => Some(MetaEventName::CuePoint),
+            0x58 => Some(MetaEventName::TimeSignature),
+            0x59 => Some(MetaEventName::KeySignature),
+            0xF0 => Some(MetaEventName::SysEx),
+            0xF7 => Some(MetaEventName::SysExEscape),
+            _ => None
+        }
+    }
 }
 
+// data is the raw bytes of the event. text events have no set encoding, so they stay bytes
+// until something shows them
+#[derive(Clone, Debug)]
 pub struct MetaEvent {
-    pub time: f32,
+    pub time: f64,
     pub meta_name: MetaEventName,
+    pub track: u16,
     pub data: Vec<u8>
 }
 
-//#[derive(PartialEq, Eq)]
+impl MetaEvent {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.data).to_string()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    // any malformed or truncated data is an error
+    Strict,
+    // tracks that run out of bytes are treated as ended, hanging notes get closed
+    Lenient
+}
+
+// which note a note off ends when the same key and channel has more than one playing
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NotePairing {
+    // the latest one
+    Lifo,
+    // the earliest one
+    Fifo,
+    // a note on ends whatever is still playing first, so there's never more than one
+    TruncatePrevious
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum MIDIEventType {
     NoteOff=0x80,
     NoteOn=0x90,
+    PolyAftertouch=0xA0,
     ControlEvent=0xB0,
+    ProgramChange=0xC0,
+    ChannelPressure=0xD0,
     PitchBend=0xE0,
+    SysEx=0xF0
+}
+
+// what a sysex message in the event stream does. its raw bytes are in the meta events,
+// this is so the synth doesn't have to look them up
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SysExKind {
+    Other=0,
+    GMReset,
+    GSReset,
+    XGReset
+}
+
+impl SysExKind {
+    // data starts after the 0xF0
+    pub fn classify(data: &[u8]) -> Self {
+        match data {
+            // gm and gm2 system on. device ids are ignored, everyone sends 0x7F anyway
+            [0x7E, _, 0x09, 0x01 | 0x03, ..] => SysExKind::GMReset,
+            [0x41, _, 0x42, 0x12, 0x40, 0x00, 0x7F, ..] => SysExKind::GSReset,
+            [0x43, dev, 0x4C, 0x00, 0x00, 0x7E, ..] if dev & 0xF0 == 0x10 => SysExKind::XGReset,
+            _ => SysExKind::Other
+        }
+    }
+
+    pub fn from_raw(kind: u8) -> Self {
+        match kind {
+            1 => SysExKind::GMReset,
+            2 => SysExKind::GSReset,
+            3 => SysExKind::XGReset,
+            _ => SysExKind::Other
+        }
+    }
 }
 
-//#[derive(PartialEq, Eq)]
+// 16 bytes, no allocations. note ons with 0 velocity are stored as note offs
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct MIDIEvent {
-    pub time: f32, // relative time
-    pub command: MIDIEventType,
-    pub data: Vec<u8>
+    pub time: f64, // relative time
+    pub status: u8,
+    pub data: [u8; 2],
+    // from the last port meta event in the track, fits in what would be padding anyway.
+    // so does the track, the synth doesn't care but midi_writer does
+    pub port: u8,
+    pub track: u16
+}
+
+impl MIDIEvent {
+    #[inline]
+    pub fn new(time: f64, track: u16, port: u8, status: u8, data1: u8, data2: u8) -> Self {
+        Self {
+            time,
+            status,
+            data: [data1, data2],
+            port,
+            track
+        }
+    }
+
+    #[inline]
+    pub fn kind(&self) -> MIDIEventType {
+        match self.status & 0xF0 {
+            0x80 => MIDIEventType::NoteOff,
+            0x90 => MIDIEventType::NoteOn,
+            0xA0 => MIDIEventType::PolyAftertouch,
+            0xB0 => MIDIEventType::ControlEvent,
+            0xC0 => MIDIEventType::ProgramChange,
+            0xD0 => MIDIEventType::ChannelPressure,
+            0xE0 => MIDIEventType::PitchBend,
+            0xF0 => MIDIEventType::SysEx,
+            s => unreachable!("event with unsupported status {:#x} was stored", s)
+        }
+    }
+
+    #[inline]
+    pub fn channel(&self) -> u8 {
+        self.status & 0x0F
+    }
+
+    // the channel counting every port's 16, same as Note::channel
+    #[inline]
+    pub fn full_channel(&self) -> u8 {
+        self.port * 16 + self.channel()
+    }
 }
 
 struct UnendedNote {
-    pub id: i32,
-    pub vel: u8
+    pub id: usize,
+    pub vel: u8,
+    pub port: u8
+}
+
+// what append_chunk marks a chunk's notes with once they're ended, ports only go up to MAX_PORTS
+const ENDED_PORT: u8 = u8::MAX;
+
+// the open note a note off on port ends. ports other than that one are left alone
+fn pop_unended(unended: &mut Vec<UnendedNote>, port: u8, pairing: NotePairing) -> Option<UnendedNote> {
+    let i = match pairing {
+        NotePairing::Fifo => unended.iter().position(|n| n.port == port)?,
+        NotePairing::Lifo | NotePairing::TruncatePrevious => unended.iter().rposition(|n| n.port == port)?
+    };
+    Some(unended.remove(i))
 }
 
-#[derive(PartialEq, Eq, Clone, Debug)]
+// a note off in a chunk that might end a note from an earlier chunk, which gets matched up in
+// append_chunk. lifo and TruncatePrevious only leave the ones that had nothing open in the chunk,
+// fifo leaves every one since the earlier chunk's notes go first
+struct OrphanOff {
+    slot: usize,
+    port: u8,
+    tick: u64,
+    // how many of the chunk's own notes on the slot came before it, fifo only
+    ons_before: usize,
+    // set for a TruncatePrevious note on instead, where the note off for the synth goes in midi_evs
+    ev_index: Option<usize>
+}
+
+// packed into 16 bytes so black midis with a billion notes stay somewhat reasonable on memory.
+// info holds the track in the upper 16 bits, then the channel and velocity a byte each.
+// the channel byte is port * 16 + channel
+#[derive(PartialEq, Eq, Clone, Copy)]
 pub struct Note {
-    pub start: u32,
-    pub end: u32,
-    pub channel: u8,
-    pub track: usize,
-    pub velocity: u8,
+    // ticks in tick based mode, microseconds otherwise
+    pub start: u64,
+    duration: u32,
+    info: u32
+}
+
+impl Note {
+    const OPEN: u32 = u32::MAX;
+    // durations from 2^31 on (about 36 minutes in microseconds) are stored in steps of 1024 with
+    // this bit set, so notes in marathon midis still fit. that's about a millisecond of precision
+    // on notes that are half an hour long, and it goes up to 25 days
+    const COARSE: u32 = 1 << 31;
+    const COARSE_SHIFT: u32 = 10;
+
+    pub fn new(start: u64, channel: u8, track: usize, velocity: u8) -> Self {
+        // the track only gets 16 bits of info
+        debug_assert!(track <= u16::MAX as usize);
+        Self {
+            start,
+            duration: Self::OPEN,
+            info: ((track as u32 & 0xFFFF) << 16) | ((channel as u32) << 8) | velocity as u32
+        }
+    }
+
+    #[inline]
+    pub fn end(&self) -> u64 {
+        if self.duration == Self::OPEN {
+            u64::MAX
+        } else if self.duration & Self::COARSE != 0 {
+            self.start + (((self.duration & !Self::COARSE) as u64) << Self::COARSE_SHIFT)
+        } else {
+            self.start + self.duration as u64
+        }
+    }
+
+    pub fn set_end(&mut self, end: u64) -> () {
+        let duration = end.saturating_sub(self.start);
+        self.duration = if duration < Self::COARSE as u64 {
+            duration as u32
+        } else {
+            // rounded up so a note never ends before its note off. anything past 25 days gets cut
+            // short there instead of turning into a note that never ends
+            let steps = (duration + (1 << Self::COARSE_SHIFT) - 1) >> Self::COARSE_SHIFT;
+            Self::COARSE | steps.min((Self::OPEN - 1 - Self::COARSE) as u64) as u32
+        };
+    }
+
+    #[inline]
+    pub fn track(&self) -> usize {
+        (self.info >> 16) as usize
+    }
+
+    #[inline]
+    pub fn channel(&self) -> u8 {
+        (self.info >> 8) as u8
+    }
+
+    #[inline]
+    pub fn velocity(&self) -> u8 {
+        self.info as u8
+    }
+
+    pub fn set_velocity(&mut self, velocity: u8) -> () {
+        self.info = (self.info & !0xFF) | velocity as u32;
+    }
+
+    // the packed fields as they are, for midi_cache
+    pub fn to_raw(&self) -> (u64, u32, u32) {
+        (self.start, self.duration, self.info)
+    }
+
+    pub fn from_raw(start: u64, duration: u32, info: u32) -> Self {
+        Self { start, duration, info }
+    }
+}
+
+impl std::fmt::Debug for Note {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Note")
+            .field("start", &self.start)
+            .field("end", &self.end())
+            .field("channel", &self.channel())
+            .field("track", &self.track())
+            .field("velocity", &self.velocity())
+            .finish()
+    }
 }
 
-pub struct MIDITrack {
-    pub rdr: BufferedByteReader,
+pub struct MIDITrack<R = File> {
+    pub rdr: TrackReader<R>,
     pub ev_count: u64,
     pub tempo_ev_count: u64,
     pub note_count: u64,
     pub ended: bool,
     prev_cmd: u8,
+    port: u8,
+    // one more than the highest port used
+    pub port_count: u8,
 
+    // everything below is recorded in ticks while parsing, convert_times turns
+    // it into seconds (or microseconds for notes) once the tempo map is known
     pub tempo_evs: Vec<TempoEvent>,
     pub midi_evs: Vec<MIDIEvent>,
     pub meta_evs: Vec<MetaEvent>,
     pub notes: Vec<Vec<Note>>,
-    note_counts: [usize; 256],
     pub track_len: u64,
-    pub track_len_p2: f64,
-    pub t_track_time: f64,
-    pub tempo_id: usize,
-    pub tempo_multi: f64,
+    pub start_tick: u64,
 
     unended_notes: Vec<Vec<UnendedNote>>,
-    unended_init: bool,
-    curr_note_idx: [usize; 256],
+    // per key, how many notes take_notes already handed out. unended note ids still count them
+    notes_taken: Vec<usize>,
 
-    valid_delta: f64, // to add delta times of skipped / unneeded events lol
-    ppq: u16,
+    division: TimeDivision,
     track_num: usize,
 
     tick_based_parsing: bool,
-    pub key_range: [u8; 2]
+    parse_mode: ParseMode,
+    note_pairing: NotePairing,
+    ev_start: usize,
+    pub warnings: Vec<String>,
+    pub key_range: [u8; 2],
+
+    // only set when the track is parsed in chunks, see track_splitter
+    end_pos: Option<usize>,
+    continuation: bool,
+    orphan_offs: Vec<OrphanOff>,
+
+    // only set by midi_validator. everything it looks for is skipped over quietly otherwise
+    pub findings: Option<Box<Findings>>
 }
 
-impl MIDITrack {
-    pub fn new(t_num: usize, ppq: u16, stream: Arc<Mutex<File>>, loc: &TrackPointer, tick_based_parsing: bool) -> Result<Self, ()> {
+impl<R: Read + Seek> MIDITrack<R> {
+    pub fn new(t_num: usize, division: TimeDivision, rdr: TrackReader<R>, tick_based_parsing: bool, parse_mode: ParseMode, note_pairing: NotePairing) -> Result<Self, MidiLoadError> {
+        let ev_start = rdr.pos();
         let mt = Self {
-            rdr: BufferedByteReader::new(stream, loc.start as usize, loc.len as usize, 100000).unwrap(),
+            rdr,
             ev_count: 0,
             tempo_ev_count: 0,
             note_count: 0,
             ended: false,
             prev_cmd: 0x00,
+            port: 0,
+            port_count: 1,
 
             tempo_evs: Vec::new(),
             midi_evs: Vec::new(),
             meta_evs: Vec::new(),
-            notes: Vec::new(),
-            note_counts: [0usize; 256],
+            notes: (0..256).map(|_| Vec::new()).collect(),
             track_len: 0,
-            track_len_p2: 0.0f64,
-            t_track_time: 0.0f64,
-            tempo_id: 0usize,
-            tempo_multi: (500000.0 / ppq as f64) / 1000000.0,
+            start_tick: 0,
 
-            unended_notes: Vec::new(),
-            unended_init: false,
-            curr_note_idx: [0usize; 256],
+            unended_notes: (0..256*16).map(|_| Vec::new()).collect(),
+            notes_taken: vec![0; 256],
 
-            valid_delta: 0.0f64,
-            ppq,
+            division,
             track_num: t_num,
 
             tick_based_parsing,
-            key_range: [255, 0]
+            parse_mode,
+            note_pairing,
+            ev_start,
+            warnings: Vec::new(),
+            key_range: [255, 0],
+
+            end_pos: None,
+            continuation: false,
+            orphan_offs: Vec::new(),
+
+            findings: None
         };
         Ok(mt)
     }
 
-    fn read_delta(&mut self) -> u64 {
-        let mut n: u64 = 0;
-        loop {
-            let b = self.rdr.read_byte().unwrap();
-            n = (n << 7) | ((b & 0x7F) as u64);
-            if (b & 0x80) == 0x00 { break; }
+    // makes this track a chunk that picks up parsing at cp, as if everything before it was already read
+    pub fn resume_at(&mut self, cp: Checkpoint) -> Result<(), MidiLoadError> {
+        let offset = cp.pos as isize - self.rdr.pos() as isize;
+        self.rdr.seek(offset, 1)?;
+        self.ev_start = cp.pos;
+        self.track_len = cp.tick;
+        self.prev_cmd = cp.status;
+        self.port = cp.port;
+        self.continuation = true;
+        Ok(())
+    }
+
+    // makes room for counts[key] more notes on every key up front
+    pub fn reserve_notes(&mut self, counts: &[usize]) {
+        for (notes, count) in self.notes.iter_mut().zip(counts) {
+            notes.reserve_exact(*count);
         }
-        n
     }
 
-    fn read_delta_time(&mut self, t_evs: &Vec<TempoEvent>) -> f64 {
-        let mut n: u64 = 0;
-        loop {
-            let b = self.rdr.read_byte().unwrap();
-            n = (n << 7) | ((b & 0x7F) as u64);
-            if (b & 0x80) == 0x00 { break; }
+    // stop parsing at pos, the next chunk takes it from there
+    pub fn limit_to(&mut self, pos: usize) -> () {
+        self.end_pos = Some(pos);
+    }
+
+    pub fn done(&self) -> bool {
+        self.ended || self.end_pos.map_or(false, |end| self.rdr.pos() >= end)
+    }
+
+    // stitches the next chunk of the same track onto this one. its stray note offs end whatever
+    // is still open here first (the same way they would have if the track was parsed in one go),
+    // then its own open notes carry over
+    pub fn append_chunk(&mut self, mut chunk: MIDITrack<R>) {
+        // fifo note offs that didn't end anything here go to the chunk's own notes. the ones that
+        // get ended are marked with an impossible port and dropped at the end, removing them one by
+        // one would go quadratic on slots with lots of notes. head skips the marked ones at the front
+        let mut head: Vec<usize> = Vec::new();
+        // TruncatePrevious note offs for the synth, they go in front of the note on that caused them
+        let mut truncated: Vec<(usize, MIDIEvent)> = Vec::new();
+        for off in std::mem::take(&mut chunk.orphan_offs) {
+            if let Some(n) = pop_unended(&mut self.unended_notes[off.slot], off.port, self.note_pairing) {
+                let note = self.note_mut(off.slot / 16, n.id);
+                note.set_end(off.tick);
+                note.set_velocity(n.vel);
+                if let Some(i) = off.ev_index {
+                    let (key, ch) = ((off.slot / 16) as u8, (off.slot % 16) as u8);
+                    truncated.push((i, MIDIEvent::new(off.tick as f64, self.track_num as u16, off.port, 0x80 | ch, key, n.vel)));
+                }
+            } else if self.note_pairing == NotePairing::Fifo {
+                if head.is_empty() {
+                    head = vec![0; chunk.unended_notes.len()];
+                }
+                let own = &mut chunk.unended_notes[off.slot];
+                let start = head[off.slot];
+                if let Some(i) = own[start..off.ons_before.max(start)].iter().position(|n| n.port == off.port) {
+                    let n = &mut own[start + i];
+                    n.port = ENDED_PORT;
+                    let (id, vel) = (n.id, n.vel);
+                    while own.get(head[off.slot]).is_some_and(|n| n.port == ENDED_PORT) {
+                        head[off.slot] += 1;
+                    }
+                    let note = chunk.note_mut(off.slot / 16, id);
+                    note.set_end(off.tick);
+                    note.set_velocity(vel);
+                }
+            }
         }
-        self.track_len_p2 += n as f64;
-
-        if self.tempo_id < t_evs.len() && self.track_len_p2 > t_evs[self.tempo_id].time as f64 {
-            let mut t: i64 = (self.track_len_p2 - n as f64) as i64;
-            let mut v: f64 = 0.0;
-            while self.tempo_id < t_evs.len() && self.track_len_p2 > t_evs[self.tempo_id].time as f64 {
-                v += ((t_evs[self.tempo_id].time as i64 - t) as f64) * self.tempo_multi;
-                t = t_evs[self.tempo_id].time as i64;
-                self.tempo_multi = (t_evs[self.tempo_id].tempo as f64 / self.ppq as f64) / 1000000.0;
-                self.tempo_id += 1;
+        if !head.is_empty() {
+            for un in chunk.unended_notes.iter_mut() {
+                un.retain(|n| n.port != ENDED_PORT);
             }
-            v += (self.track_len_p2 - t as f64) * self.tempo_multi;
-            return v;
-        } else {
-            return (n as f64) * self.tempo_multi;
+        }
+        if !truncated.is_empty() {
+            let mut evs = Vec::with_capacity(chunk.midi_evs.len() + truncated.len());
+            let mut copied = 0;
+            for (i, ev) in truncated {
+                evs.extend_from_slice(&chunk.midi_evs[copied..i]);
+                evs.push(ev);
+                copied = i;
+            }
+            evs.extend_from_slice(&chunk.midi_evs[copied..]);
+            chunk.midi_evs = evs;
+        }
+
+        for (slot, un) in chunk.unended_notes.iter_mut().enumerate() {
+            let offset = self.notes[slot / 16].len() + self.notes_taken[slot / 16];
+            self.unended_notes[slot].extend(un.drain(..).map(|n| UnendedNote { id: n.id + offset, ..n }));
+        }
+        for (notes, chunk_notes) in self.notes.iter_mut().zip(chunk.notes.iter_mut()) {
+            notes.append(chunk_notes);
         }
 
+        self.midi_evs.append(&mut chunk.midi_evs);
+        self.meta_evs.append(&mut chunk.meta_evs);
+        self.tempo_evs.append(&mut chunk.tempo_evs);
+        self.warnings.append(&mut chunk.warnings);
+
+        self.ev_count += chunk.ev_count;
+        self.tempo_ev_count += chunk.tempo_ev_count;
+        self.note_count += chunk.note_count;
+        self.key_range[0] = self.key_range[0].min(chunk.key_range[0]);
+        self.key_range[1] = self.key_range[1].max(chunk.key_range[1]);
+        self.port_count = self.port_count.max(chunk.port_count);
+
+        self.track_len = chunk.track_len;
+        self.ended = chunk.ended;
+        self.end_pos = chunk.end_pos;
     }
 
-    pub fn parse_ev(&mut self) -> Result<(), ()> {
-        if self.ended { 
-            return Ok(())
+    // parses every event before tick and stops right in front of the first one at or after it.
+    // returns that event's tick, or None once the track has ended. used by MIDIStream
+    pub fn parse_until(&mut self, tick: u64) -> Result<Option<u64>, MidiLoadError> {
+        while !self.ended {
+            let pos = self.rdr.pos();
+            let track_len = self.track_len;
+            let next = self.read_delta().map(|delta| track_len + delta);
+            self.rdr.seek(pos as isize - self.rdr.pos() as isize, 1)?;
+
+            match next {
+                Ok(next) if next >= tick => return Ok(Some(next)),
+                // a delta that can't be read is left for parse_ev to run into and report
+                _ => if let Err(e) = self.parse_ev() {
+                    self.recover(e)?;
+                }
+            }
         }
-        let delta = self.read_delta();
-        self.track_len += delta;
+        Ok(None)
+    }
 
-        let mut command: u8 = self.rdr.read_byte().unwrap();
-        if command < 0x80 {
-            self.rdr.seek(-1, 1).unwrap();
-            command = self.prev_cmd;
-        }
+    // start of the earliest note on this key that hasn't ended yet
+    pub fn first_open_start(&self, key: usize) -> Option<u64> {
+        self.unended_notes[key * 16..key * 16 + 16].iter()
+            .filter_map(|un| un.first())
+            .map(|n| self.notes[key][n.id - self.notes_taken[key]].start)
+            .min()
+    }
 
-        self.prev_cmd = command;
+    // hands out the first count notes of a key. they have to be ended already
+    pub fn take_notes(&mut self, key: usize, count: usize) -> Vec<Note> {
+        self.notes_taken[key] += count;
+        self.notes[key].drain(..count).collect()
+    }
 
-        match command & 0xF0 {
-            0x80 => {
-                self.rdr.skip_bytes(2)?;
-            },
-            0x90 => {
-                let key = self.rdr.read_byte()?;
-                if key <= self.key_range[0] {
-                    self.key_range[0] = key;
-                }
-                if key >= self.key_range[1] {
-                    self.key_range[1] = key;
-                }
+    #[inline]
+    fn note_mut(&mut self, key: usize, id: usize) -> &mut Note {
+        &mut self.notes[key][id - self.notes_taken[key]]
+    }
 
-                if self.rdr.read_byte()? > 0 { 
-                    self.note_count += 1;
-                    self.note_counts[key as usize] += 1;
+    // called when parse_ev fails. in lenient mode a track that ran out of bytes
+    // is treated as ended instead of failing the whole file
+    pub fn recover(&mut self, e: MidiLoadError) -> Result<(), MidiLoadError> {
+        match e {
+            MidiLoadError::TruncatedTrack { .. } if self.parse_mode == ParseMode::Lenient => {
+                let ev_start = self.ev_start;
+                if self.rdr.at_end() && ev_start == self.rdr.pos() {
+                    self.warnings.push(format!("track {} has no end-of-track event", self.track_num));
+                    self.report(FindingKind::MissingEndOfTrack, self.track_len, || String::from("the track runs out without one"));
+                } else {
+                    self.warnings.push(format!("track {} is truncated in the middle of an event at byte {}", self.track_num, ev_start));
+                    self.report(FindingKind::MissingEndOfTrack, self.track_len, || format!("the track is cut off in the middle of an event at byte {}", ev_start));
                 }
+                self.ended = true;
+                Ok(())
             },
-            0xA0 | 0xB0 | 0xE0 => {
-                self.rdr.skip_bytes(2)?;
-            },
-            0xC0 | 0xD0 => {
-                self.rdr.skip_bytes(1)?;
-            },
-            0xF0 => {
-                match command {
-                    0xFF => {
-                        let cmd2: u8 = self.rdr.read_byte()?;
-                        let val = self.read_delta() as usize;
-                        
-                        match cmd2 {
-                            0x00 => { self.rdr.skip_bytes(2)?; }
-                            0x01..=0x07 | 0x0A => {
-                                self.rdr.skip_bytes(val)?;
-                            }
-                            0x7F => { self.rdr.skip_bytes(val)?; }
-                            0x20 => { self.rdr.skip_bytes(1)?; }
-                            0x21 => { self.rdr.skip_bytes(1)?; }
-                            0x2F => { self.ended = true; }
-                            0x51 => {
-                                let mut tempo: u32 = 0;
-                                for _ in 0..3 {
-                                    tempo = (tempo << 8) | (self.rdr.read_byte()? as u32);
-                                }
+            e => Err(e.in_track(self.track_num))
+        }
+    }
 
-                                self.tempo_evs.push(
-                                    TempoEvent {
-                                        time: self.track_len,
-                                        time_norm: 0.0,
-                                        tempo
-                                    }
-                                );
-                                self.tempo_ev_count += 1;
-                            }
-                            0x54 => { self.rdr.skip_bytes(5)?; }
-                            0x58 => { self.rdr.skip_bytes(4)?; }
-                            0x59 => { self.rdr.skip_bytes(2)?; }
-                            _ => {
-                                println!("unknown sys ev {}", cmd2);
-                                self.rdr.skip_bytes(val)?;
-                                self.ev_count -= 1;
-                            }
-                        };
-                    }
-                    0xF0 => {
-                        let sysex_len = self.read_delta();
-                        self.rdr.skip_bytes(sysex_len as usize)?;
-                    }
-                    0xF2 => {
-                        self.rdr.skip_bytes(2)?;
-                    }
-                    0xF3 => {
-                        self.rdr.skip_bytes(1)?;
-                    },
-                    0xF7 => {
-                        let sysex_len = self.read_delta();
-                        self.rdr.skip_bytes(sysex_len as usize)?;
-                    }
-                    _ => {}
+    // lenient mode: ends every note that never got a note off at the last tick seen in the track
+    pub fn close_hanging_notes(&mut self) -> () {
+        if self.parse_mode != ParseMode::Lenient {
+            return;
+        }
+
+        let end = self.track_len;
+
+        let mut closed = 0;
+        for (i, un) in self.unended_notes.iter_mut().enumerate() {
+            let key = i / 16;
+            let ch = (i % 16) as u8;
+            while let Some(n) = un.pop() {
+                let note = &mut self.notes[key][n.id - self.notes_taken[key]];
+                if let Some(findings) = self.findings.as_mut() {
+                    findings.add(FindingKind::UnclosedNote, self.track_num, note.start, || format!("key {} channel {} has no note off", key, ch + 1));
                 }
-            },
-            _ => {}
+                note.set_end(end);
+                note.set_velocity(n.vel);
+                self.midi_evs.push(MIDIEvent::new(end as f64, self.track_num as u16, n.port, 0x80 | ch, key as u8, n.vel));
+                closed += 1;
+            }
+        }
+
+        if closed > 0 {
+            self.warnings.push(format!("closed {} hanging notes in track {}", closed, self.track_num));
         }
-        self.ev_count += 1;
-        Ok(())
     }
 
-    pub fn prep_for_pass_two(&mut self) -> Result<(),()> {
-        //reset rdr i think
-        self.rdr.seek(0, 0).unwrap();
-        self.prev_cmd = 0x00;
-        self.ended = false;
+    fn read_delta(&mut self) -> Result<u64, MidiLoadError> {
+        let mut n: u64 = 0;
+        loop {
+            let b = self.rdr.read_byte()?;
+            n = (n << 7) | ((b & 0x7F) as u64);
+            if (b & 0x80) == 0x00 { break; }
+        }
+        Ok(n)
+    }
 
-        for _ in 0..256 {
-            self.notes.push(Vec::new());
+    // the checks midi_validator turns on. they're all cold, a valid midi never gets here
+    #[cold]
+    fn report(&mut self, kind: FindingKind, tick: u64, detail: impl FnOnce() -> String) -> () {
+        let track = self.track_num;
+        if let Some(findings) = self.findings.as_mut() {
+            findings.add(kind, track, tick, detail);
         }
+    }
 
-        Ok(())
+    #[cold]
+    fn report_data(&mut self, status: u8, data: &[u8]) -> () {
+        let bad = data.iter().filter(|b| **b & 0x80 != 0).map(|b| format!("{:#04X}", b)).collect::<Vec<_>>().join(", ");
+        self.report(FindingKind::DataOutOfRange, self.track_len, || format!("{} in a {:#04X} event, data bytes only go up to 0x7F", bad, status));
     }
 
-    pub fn parse_pass_two(&mut self, t_evs: &Vec<TempoEvent>) -> Result<(),()> {
-        if self.ended {
-            return Ok(())
+    // a note on for a key and channel that's still playing from an earlier note on
+    #[cold]
+    fn check_overlap(&mut self, key: u8, ch: u8) -> () {
+        let port = self.port;
+        let open = self.unended_notes[key as usize * 16 + ch as usize].iter().rev().find(|n| n.port == port).map(|n| n.id);
+        if let Some(id) = open {
+            let since = self.note_mut(key as usize, id).start;
+            self.report(FindingKind::OverlappingNotes, self.track_len, || format!("key {} channel {} starts again while the one from tick {} is still playing", key, ch + 1, since));
         }
+    }
 
-        if !self.unended_init {
-            for _ in 0..256*16 {
-                self.unended_notes.push(Vec::new());
-            }
-            self.unended_init = true;
+    #[cold]
+    fn check_meta_len(&mut self, kind: u8, len: usize) -> () {
+        if len > self.rdr.remaining() {
+            self.report(FindingKind::BadMetaLength, self.track_len, || format!("{:#04X} meta event is {} bytes long, past the end of the track", kind, len));
+            return;
+        }
+        let expected: &[usize] = match kind {
+            0x00 => &[0, 2],
+            0x20 | 0x21 => &[1],
+            0x2F => &[0],
+            0x51 => &[3],
+            0x54 => &[5],
+            0x58 => &[4],
+            0x59 => &[2],
+            _ => return
+        };
+        if !expected.contains(&len) {
+            self.report(FindingKind::BadMetaLength, self.track_len, || format!("{:#04X} meta event is {} bytes long instead of {}", kind, len, expected[expected.len() - 1]));
         }
+    }
+
+    // the note a note off on key and channel ends, if there's one
+    fn end_note(&mut self, key: u8, ch: u8) {
+        let slot = key as usize * 16 + ch as usize;
+        // an earlier chunk's notes would go before anything open in this one
+        if self.continuation && self.note_pairing == NotePairing::Fifo {
+            let ons_before = self.unended_notes[slot].len();
+            self.orphan_offs.push(OrphanOff { slot, port: self.port, tick: self.track_len, ons_before, ev_index: None });
+            return;
+        }
+
+        if let Some(n) = pop_unended(&mut self.unended_notes[slot], self.port, self.note_pairing) {
+            let track_len = self.track_len;
+            let note = self.note_mut(key as usize, n.id);
+            note.set_end(track_len);
+            note.set_velocity(n.vel);
+        } else if self.continuation {
+            self.orphan_offs.push(OrphanOff { slot, port: self.port, tick: self.track_len, ons_before: 0, ev_index: None });
+        } else {
+            self.report(FindingKind::UnmatchedNoteOff, self.track_len, || format!("key {} channel {} isn't playing", key, ch + 1));
+        }
+    }
+
+    // event times get stored as ticks for now, see convert_times
+    #[inline]
+    fn tick(&self) -> f64 {
+        self.track_len as f64
+    }
+
+    pub fn parse_ev(&mut self) -> Result<(), MidiLoadError> {
+        if self.ended { 
+            return Ok(())
+        }
+
+        self.ev_start = self.rdr.pos();
+        let delta = self.read_delta()?;
+        self.track_len += delta;
 
-        let delta = self.read_delta_time(t_evs);
-        self.valid_delta += delta;
-        self.t_track_time += delta;
-        let mut command: u8 = self.rdr.read_byte().unwrap();
+        let mut command: u8 = self.rdr.read_byte()?;
         if command < 0x80 {
-            self.rdr.seek(-1, 1).unwrap();
+            self.rdr.seek(-1, 1)?;
             command = self.prev_cmd;
+            // only channel events carry over
+            if !(0x80..0xF0).contains(&command) {
+                self.report(FindingKind::RunningStatus, self.track_len, || match command {
+                    0x00 => String::from("data byte with no status byte before it"),
+                    _ => format!("data byte after a {:#04X} event", command)
+                });
+            }
         }
 
         self.prev_cmd = command;
@@ -295,163 +702,176 @@ impl MIDITrack {
         let ch: u8 = command & 0x0F;
         match c {
             0x80 => {
-                //self.rdr.skip_bytes(2)?;
                 let key = self.rdr.read_byte()?;
-                let mut vel = self.rdr.read_byte()?;
-
-                let un = &mut self.unended_notes[key as usize * 16 + ch as usize];
-                if un.len() != 0 {
-                    let n = un.pop().unwrap();
-                    if n.id != -1 {
-                        self.notes[key as usize][n.id as usize].end = if self.tick_based_parsing {
-                            self.track_len_p2 as u32
-                        } else {
-                            (self.t_track_time * 1000000.0) as u32
-                        };
-                        self.notes[key as usize][n.id as usize].velocity = n.vel;
-                        vel = n.vel;
-                    }
+                let vel = self.rdr.read_byte()?;
+                if (key | vel) & 0x80 != 0 {
+                    self.report_data(command, &[key, vel]);
                 }
 
-                self.midi_evs.push(
-                    MIDIEvent {
-                        time: self.t_track_time as f32,
-                        command: MIDIEventType::NoteOff,
-                        data: vec![ch, key, vel]
-                    }
-                );
-                self.valid_delta = 0.0;
+                self.end_note(key, ch);
+                self.midi_evs.push(MIDIEvent::new(self.tick(), self.track_num as u16, self.port, 0x80 | ch, key, vel));
             },
             0x90 => {
                 let key = self.rdr.read_byte()?;
                 let vel = self.rdr.read_byte()?;
-                self.midi_evs.push(
-                    MIDIEvent {
-                        time: self.t_track_time as f32,
-                        command: if vel > 0 { MIDIEventType::NoteOn } else { MIDIEventType::NoteOff },
-                        data: vec![ch, key, vel]
-                    }
-                );
-
+                if (key | vel) & 0x80 != 0 {
+                    self.report_data(command, &[key, vel]);
+                }
+                if key <= self.key_range[0] {
+                    self.key_range[0] = key;
+                }
+                if key >= self.key_range[1] {
+                    self.key_range[1] = key;
+                }
                 if vel == 0 {
-                    let un = &mut self.unended_notes[key as usize * 16 + ch as usize];
-                    if un.len() != 0 {
-                        let n = un.pop().unwrap();
-                        if n.id != -1 {
-                            self.notes[key as usize][n.id as usize].end = if self.tick_based_parsing {
-                                self.track_len_p2 as u32
-                            } else {
-                                (self.t_track_time * 1000000.0) as u32
-                            };
-                            self.notes[key as usize][n.id as usize].velocity = n.vel;
-                            
+                    self.end_note(key, ch);
+                    self.midi_evs.push(MIDIEvent::new(self.tick(), self.track_num as u16, self.port, 0x80 | ch, key, vel));
+                } else {
+                    if self.note_pairing == NotePairing::TruncatePrevious {
+                        let slot = key as usize * 16 + ch as usize;
+                        if let Some(n) = pop_unended(&mut self.unended_notes[slot], self.port, self.note_pairing) {
+                            let track_len = self.track_len;
+                            let note = self.note_mut(key as usize, n.id);
+                            note.set_end(track_len);
+                            note.set_velocity(n.vel);
+                            // so the synth lets go of it too
+                            self.midi_evs.push(MIDIEvent::new(self.tick(), self.track_num as u16, self.port, 0x80 | ch, key, n.vel));
+                        } else if self.continuation {
+                            let ev_index = Some(self.midi_evs.len());
+                            self.orphan_offs.push(OrphanOff { slot, port: self.port, tick: self.track_len, ons_before: 0, ev_index });
                         }
                     }
-                } else {
+                    if self.findings.is_some() {
+                        self.check_overlap(key, ch);
+                    }
+                    self.midi_evs.push(MIDIEvent::new(self.tick(), self.track_num as u16, self.port, 0x90 | ch, key, vel));
+                    self.note_count += 1;
                     self.unended_notes[key as usize * 16 + ch as usize].push(UnendedNote {
-                        id: self.curr_note_idx[key as usize] as i32,
-                        vel
+                        id: self.notes[key as usize].len() + self.notes_taken[key as usize],
+                        vel,
+                        port: self.port
                     });
-                    /*self.notes[key as usize][self.curr_note_idx[key as usize]] = Note {
-                        start: (self.t_track_time * 1000000.0) as u64,
-                        end: 1000000000000,
-                        channel: ch
-                    };*/
-                    //self.notes[key as usize][self.curr_note_idx[key as usize]].start = (self.t_track_time * 1000000.0) as u64;
-                    //self.notes[key as usize][self.curr_note_idx[key as usize]].channel = ch;
-                    self.notes[key as usize].push(Note {
-                        start: if self.tick_based_parsing {
-                            self.track_len_p2 as u32
-                        } else {
-                            (self.t_track_time * 1000000.0) as u32
-                        },
-                        end: 10000000,
-                        channel: ch,
-                        track: self.track_num,
-                        velocity: 0
-                    });
-                    self.curr_note_idx[key as usize] += 1;
+                    // stays open until its note off shows up
+                    self.notes[key as usize].push(Note::new(self.track_len, self.port * 16 + ch, self.track_num, 0));
                 }
-
-                self.valid_delta = 0.0;
             },
             0xB0 => {
                 let ctrl_num = self.rdr.read_byte()?;
                 let ctrl_val = self.rdr.read_byte()?;
-                self.midi_evs.push(MIDIEvent {
-                    time: self.t_track_time as f32,
-                    command: MIDIEventType::ControlEvent,
-                    data: vec![ch, ctrl_num, ctrl_val]
-                });
-                
-                self.valid_delta = 0.0;
+                if (ctrl_num | ctrl_val) & 0x80 != 0 {
+                    self.report_data(command, &[ctrl_num, ctrl_val]);
+                }
+                self.midi_evs.push(MIDIEvent::new(self.tick(), self.track_num as u16, self.port, 0xB0 | ch, ctrl_num, ctrl_val));
             },
             0xE0 => {
                 let v1 = self.rdr.read_byte()?;
                 let v2 = self.rdr.read_byte()?;
-                self.midi_evs.push(MIDIEvent {
-                    time: self.t_track_time as f32,
-                    command: MIDIEventType::PitchBend,
-                    data: vec![ch, v1, v2]
-                });
-                
-                self.valid_delta = 0.0;
+                if (v1 | v2) & 0x80 != 0 {
+                    self.report_data(command, &[v1, v2]);
+                }
+                self.midi_evs.push(MIDIEvent::new(self.tick(), self.track_num as u16, self.port, 0xE0 | ch, v1, v2));
             },
             0xA0 => {
-               self.rdr.skip_bytes(2)?;
+                let key = self.rdr.read_byte()?;
+                let pressure = self.rdr.read_byte()?;
+                if (key | pressure) & 0x80 != 0 {
+                    self.report_data(command, &[key, pressure]);
+                }
+                self.midi_evs.push(MIDIEvent::new(self.tick(), self.track_num as u16, self.port, 0xA0 | ch, key, pressure));
             },
             0xC0 | 0xD0 => {
-                self.rdr.skip_bytes(1)?;
+                let v = self.rdr.read_byte()?;
+                if v & 0x80 != 0 {
+                    self.report_data(command, &[v]);
+                }
+                self.midi_evs.push(MIDIEvent::new(self.tick(), self.track_num as u16, self.port, c | ch, v, 0));
             },
             0xF0 => {
                 match command {
                     0xFF => {
                         let cmd2: u8 = self.rdr.read_byte()?;
-                        let val = self.read_delta() as usize;
+                        let val = self.read_delta()? as usize;
+                        if self.findings.is_some() {
+                            self.check_meta_len(cmd2, val);
+                        }
                         
                         match cmd2 {
-                            0x00 => { self.rdr.skip_bytes(2)?; }
-                            // marker
-                            0x06 => {
-                                let mut text_bytes: Vec<u8> = vec![0u8; val];
-                                self.rdr.read(&mut text_bytes[0..val], val).unwrap();
+                            // text events, time and key signatures
+                            0x01..=0x07 | 0x58 | 0x59 => {
+                                // a broken length doesn't get to allocate, skipping it runs into the end of the track instead
+                                if val > self.rdr.remaining() {
+                                    self.rdr.skip_bytes(val)?;
+                                }
+                                let mut data: Vec<u8> = vec![0u8; val];
+                                self.rdr.read(&mut data[0..val], val)?;
                                 self.meta_evs.push(MetaEvent {
-                                    time: self.t_track_time as f32,
-                                    meta_name: MetaEventName::Marker,
-                                    data: text_bytes
+                                    time: self.tick(),
+                                    meta_name: MetaEventName::from_raw(cmd2).unwrap(),
+                                    track: self.track_num as u16,
+                                    data
                                 });
                             }
-                            0x01..=0x05 | 0x07 | 0x0A => {
+                            // midi port, everything after it in this track goes there
+                            0x21 => {
+                                if val >= 1 {
+                                    self.port = self.rdr.read_byte()?.min(MAX_PORTS - 1);
+                                    self.port_count = self.port_count.max(self.port + 1);
+                                    self.rdr.skip_bytes(val - 1)?;
+                                }
+                            }
+                            // always go by the length in the event, track_splitter relies on that
+                            0x00 | 0x0A | 0x20 | 0x54 | 0x7F => {
                                 self.rdr.skip_bytes(val)?;
                             }
-                            0x7F => { self.rdr.skip_bytes(val)?; }
-                            0x20 => { self.rdr.skip_bytes(1)?; }
-                            0x21 => { self.rdr.skip_bytes(1)?; }
                             0x2F => { self.ended = true; }
                             0x51 => {
                                 let mut tempo: u32 = 0;
                                 for _ in 0..3 {
                                     tempo = (tempo << 8) | (self.rdr.read_byte()? as u32);
                                 }
-
-                                self.tempo_evs.push(TempoEvent {
-                                    time: self.track_len_p2 as u64,
-                                    time_norm: self.t_track_time as f32,
-                                    tempo
-                                });
+                                self.rdr.skip_bytes(val.saturating_sub(3))?;
+
+                                // tempo has no effect on SMPTE timing
+                                if !self.division.is_smpte() {
+                                    self.tempo_evs.push(
+                                        TempoEvent {
+                                            time: self.track_len,
+                                            time_norm: 0.0,
+                                            tempo
+                                        }
+                                    );
+                                    self.tempo_ev_count += 1;
+                                }
                             }
-                            0x54 => { self.rdr.skip_bytes(5)?; }
-                            0x58 => { self.rdr.skip_bytes(4)?; }
-                            0x59 => { self.rdr.skip_bytes(2)?; }
                             _ => {
                                 println!("unknown sys ev {}", cmd2);
                                 self.rdr.skip_bytes(val)?;
+                                // doesn't count as an event. it can be the first one in the track,
+                                // so there's nothing to take back from ev_count
+                                return Ok(());
                             }
                         };
                     }
-                    0xF0 => {
-                        let sysex_len = self.read_delta();
-                        self.rdr.skip_bytes(sysex_len as usize)?;
+                    0xF0 | 0xF7 => {
+                        let sysex_len = self.read_delta()? as usize;
+                        if sysex_len > self.rdr.remaining() {
+                            self.rdr.skip_bytes(sysex_len)?;
+                        }
+                        let mut data: Vec<u8> = vec![0u8; sysex_len];
+                        self.rdr.read(&mut data[0..sysex_len], sysex_len)?;
+
+                        // escapes are either the rest of a split up sysex or something raw
+                        // that's not worth guessing at, only whole messages get looked at
+                        if command == 0xF0 {
+                            let kind = SysExKind::classify(&data);
+                            self.midi_evs.push(MIDIEvent::new(self.tick(), self.track_num as u16, self.port, 0xF0, kind as u8, 0));
+                        }
+                        self.meta_evs.push(MetaEvent {
+                            time: self.tick(),
+                            meta_name: MetaEventName::from_raw(command).unwrap(),
+                            track: self.track_num as u16,
+                            data
+                        });
                     }
                     0xF2 => {
                         self.rdr.skip_bytes(2)?;
@@ -459,15 +879,86 @@ impl MIDITrack {
                     0xF3 => {
                         self.rdr.skip_bytes(1)?;
                     },
-                    0xF7 => {
-                        let sysex_len = self.read_delta();
-                        self.rdr.skip_bytes(sysex_len as usize)?;
-                    }
                     _ => {}
                 }
             },
             _ => {}
         }
+        self.ev_count += 1;
         Ok(())
     }
-}
\ No newline at end of file
+
+    // second half of parsing, once every track is read and the tempo map is merged.
+    // format 2 tracks also get shifted by start_tick here
+    pub fn convert_times(&mut self, tempo_evs: &[TempoEvent]) -> () {
+        let offset = self.start_tick;
+        let division = self.division;
+
+        convert_event_times(&mut self.midi_evs, tempo_evs, division, offset);
+        convert_meta_times(&mut self.meta_evs, tempo_evs, division, offset);
+        for notes in self.notes.iter_mut() {
+            convert_note_times(notes, tempo_evs, division, offset, self.tick_based_parsing);
+        }
+    }
+}
+
+pub fn convert_event_times(evs: &mut [MIDIEvent], tempo_evs: &[TempoEvent], division: TimeDivision, offset: u64) -> () {
+    for ev in evs.iter_mut() {
+        ev.time = ticks_to_seconds(tempo_evs, division, ev.time as u64 + offset);
+    }
+}
+
+pub fn convert_meta_times(evs: &mut [MetaEvent], tempo_evs: &[TempoEvent], division: TimeDivision, offset: u64) -> () {
+    for ev in evs.iter_mut() {
+        ev.time = ticks_to_seconds(tempo_evs, division, ev.time as u64 + offset);
+    }
+}
+
+// notes stay in ticks in tick based mode, everything else goes to microseconds
+pub fn convert_note_times(notes: &mut [Note], tempo_evs: &[TempoEvent], division: TimeDivision, offset: u64, tick_based: bool) -> () {
+    let to_micros = |tick: u64| (ticks_to_seconds(tempo_evs, division, tick + offset) * 1000000.0) as u64;
+
+    for note in notes.iter_mut() {
+        let end = note.end();
+        if tick_based {
+            note.start += offset;
+        } else {
+            note.start = to_micros(note.start);
+        }
+        if end != u64::MAX {
+            note.set_end(if tick_based {
+                end + offset
+            } else {
+                to_micros(end)
+            });
+        }
+    }
+}
+
+// where an absolute tick lands in seconds. tempo_evs has to be sorted with time_norm filled in,
+// a tempo change only affects the ticks after it
+pub fn ticks_to_seconds(tempo_evs: &[TempoEvent], division: TimeDivision, tick: u64) -> f64 {
+    let idx = tempo_evs.partition_point(|t| t.time <= tick);
+    if idx == 0 {
+        return tick as f64 * division.seconds_per_tick(500000);
+    }
+
+    let t = &tempo_evs[idx - 1];
+    t.time_norm + (tick - t.time) as f64 * division.seconds_per_tick(t.tempo)
+}
+
+// the other way around, for writing midis back out. rounds to the closest tick
+pub fn seconds_to_ticks(tempo_evs: &[TempoEvent], division: TimeDivision, seconds: f64) -> u64 {
+    let idx = tempo_evs.partition_point(|t| t.time_norm <= seconds);
+    let (base_tick, base_time, tempo) = match idx {
+        0 => (0, 0.0, 500000),
+        _ => (tempo_evs[idx - 1].time, tempo_evs[idx - 1].time_norm, tempo_evs[idx - 1].tempo)
+    };
+
+    // a tempo of 0 stops time, everything in it is on the tempo event's tick
+    let spt = division.seconds_per_tick(tempo);
+    if spt <= 0.0 {
+        return base_tick;
+    }
+    base_tick + ((seconds - base_time) / spt).round().max(0.0) as u64
+}
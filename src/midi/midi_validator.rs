@@ -0,0 +1,248 @@
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use rayon::prelude::*;
+
+use crate::midi::load_progress::{LoadProgress, LoadStage};
+use crate::midi::midi_error::MidiLoadError;
+use crate::midi::midi_file::{self, MIDIFile};
+use crate::midi::midi_track_parser::{MIDIEvent, MetaEvent, MetaEventName, Note, NotePairing, ParseMode, TempoEvent};
+use crate::midi::midi_writer::{ExportStats, MIDIExport, SMFFormat};
+use crate::midi::note_filter::NoteFilter;
+
+// black midis can have millions of the same problem, past this many per track only the count goes up
+pub const MAX_KEPT_PER_KIND: u64 = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FindingKind {
+    UnmatchedNoteOff = 0,
+    UnclosedNote,
+    OverlappingNotes,
+    RunningStatus,
+    BadMetaLength,
+    MissingEndOfTrack,
+    DataOutOfRange
+}
+
+impl FindingKind {
+    pub const ALL: [FindingKind; 7] = [
+        FindingKind::UnmatchedNoteOff,
+        FindingKind::UnclosedNote,
+        FindingKind::OverlappingNotes,
+        FindingKind::RunningStatus,
+        FindingKind::BadMetaLength,
+        FindingKind::MissingEndOfTrack,
+        FindingKind::DataOutOfRange
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FindingKind::UnmatchedNoteOff => "Unmatched note off",
+            FindingKind::UnclosedNote => "Note never closed",
+            FindingKind::OverlappingNotes => "Overlapping notes",
+            FindingKind::RunningStatus => "Running status misuse",
+            FindingKind::BadMetaLength => "Bad meta event length",
+            FindingKind::MissingEndOfTrack => "Missing end of track",
+            FindingKind::DataOutOfRange => "Data byte out of range"
+        }
+    }
+
+    // for the json export
+    pub fn id(&self) -> &'static str {
+        match self {
+            FindingKind::UnmatchedNoteOff => "unmatched_note_off",
+            FindingKind::UnclosedNote => "unclosed_note",
+            FindingKind::OverlappingNotes => "overlapping_notes",
+            FindingKind::RunningStatus => "running_status",
+            FindingKind::BadMetaLength => "bad_meta_length",
+            FindingKind::MissingEndOfTrack => "missing_end_of_track",
+            FindingKind::DataOutOfRange => "data_out_of_range"
+        }
+    }
+}
+
+pub struct Finding {
+    pub kind: FindingKind,
+    pub track: usize,
+    pub tick: u64,
+    pub detail: String
+}
+
+// what a MIDITrack collects while it's being validated, see MIDITrack::findings
+#[derive(Default)]
+pub struct Findings {
+    pub list: Vec<Finding>,
+    pub counts: [u64; FindingKind::ALL.len()]
+}
+
+impl Findings {
+    // detail only gets made for the ones that are kept
+    pub fn add(&mut self, kind: FindingKind, track: usize, tick: u64, detail: impl FnOnce() -> String) -> () {
+        let count = &mut self.counts[kind as usize];
+        *count += 1;
+        if *count <= MAX_KEPT_PER_KIND {
+            self.list.push(Finding { kind, track, tick, detail: detail() });
+        }
+    }
+}
+
+pub struct ValidationReport {
+    pub path: String,
+    pub track_count: usize,
+    // sorted by track, then tick
+    pub findings: Vec<Finding>,
+    // every finding, including the ones past MAX_KEPT_PER_KIND
+    pub counts: [u64; FindingKind::ALL.len()],
+    // problems with the file itself rather than a track
+    pub file_warnings: Vec<String>
+}
+
+impl ValidationReport {
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, "Validation report for {}", self.path);
+        let _ = writeln!(s, "{} tracks, {} problems found", self.track_count, self.total());
+        for w in self.file_warnings.iter() {
+            let _ = writeln!(s, "file: {}", w);
+        }
+        for kind in FindingKind::ALL {
+            let _ = writeln!(s, "{}: {}", kind.name(), self.counts[kind as usize]);
+        }
+        if self.findings.len() as u64 != self.total() {
+            let _ = writeln!(s, "(only the first {} of each kind per track are listed)", MAX_KEPT_PER_KIND);
+        }
+        s.push('\n');
+        for f in self.findings.iter() {
+            let _ = writeln!(s, "track {}, tick {}: {}: {}", f.track, f.tick, f.kind.name(), f.detail);
+        }
+        s
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = String::new();
+        let _ = write!(s, "{{\n  \"path\": \"{}\",\n  \"track_count\": {},\n  \"total\": {},\n", json_escape(&self.path), self.track_count, self.total());
+        s.push_str("  \"file_warnings\": [");
+        for (i, w) in self.file_warnings.iter().enumerate() {
+            let _ = write!(s, "{}\"{}\"", if i == 0 { "" } else { ", " }, json_escape(w));
+        }
+        s.push_str("],\n  \"counts\": {");
+        for (i, kind) in FindingKind::ALL.iter().enumerate() {
+            let _ = write!(s, "{}\"{}\": {}", if i == 0 { "" } else { ", " }, kind.id(), self.counts[*kind as usize]);
+        }
+        s.push_str("},\n  \"findings\": [");
+        for (i, f) in self.findings.iter().enumerate() {
+            let _ = write!(s, "{}\n    {{\"kind\": \"{}\", \"track\": {}, \"tick\": {}, \"detail\": \"{}\"}}",
+                if i == 0 { "" } else { "," }, f.kind.id(), f.track, f.tick, json_escape(&f.detail));
+        }
+        s.push_str(if self.findings.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" });
+        s
+    }
+}
+
+fn json_escape(text: &str) -> String {
+    let mut s = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\r' => s.push_str("\\r"),
+            '\t' => s.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(s, "\\u{:04x}", c as u32); }
+            c => s.push(c)
+        }
+    }
+    s
+}
+
+// reads every track in one go with the checks turned on. nothing is kept but the findings,
+// tracks get dropped as soon as they're done
+pub fn validate(path: &str, zip_entry: Option<&str>, progress: Arc<LoadProgress>) -> Result<ValidationReport, MidiLoadError> {
+    let mid: MIDIFile = MIDIFile::open(path, zip_entry, true, ParseMode::Lenient, NotePairing::Lifo, Arc::clone(&progress))?;
+    progress.set_stage(LoadStage::Parsing);
+
+    let per_track = (0..mid.trk_count as usize).into_par_iter().map(|i| {
+        let mut track = mid.new_track(i)?;
+        track.findings = Some(Box::default());
+
+        let mut counted_pos = track.rdr.pos();
+        let mut ev_num: u32 = 0;
+        while !track.ended {
+            if let Err(e) = track.parse_ev() {
+                track.recover(e)?;
+            }
+            ev_num = ev_num.wrapping_add(1);
+            if ev_num % midi_file::PROGRESS_INTERVAL == 0 {
+                let pos = track.rdr.pos();
+                progress.bytes_read.fetch_add(pos.saturating_sub(counted_pos) as u64, Ordering::Relaxed);
+                counted_pos = pos;
+                if progress.is_cancelled() {
+                    return Err(MidiLoadError::Cancelled);
+                }
+            }
+        }
+        track.close_hanging_notes();
+        progress.bytes_read.fetch_add(track.rdr.pos().saturating_sub(counted_pos) as u64, Ordering::Relaxed);
+        progress.tracks_done.fetch_add(1, Ordering::Relaxed);
+        Ok(track.findings.take().unwrap())
+    }).collect::<Result<Vec<_>, MidiLoadError>>()?;
+
+    let mut findings = Vec::new();
+    let mut counts = [0; FindingKind::ALL.len()];
+    for mut track in per_track {
+        for (total, count) in counts.iter_mut().zip(track.counts) {
+            *total += count;
+        }
+        // kinds get found in different places, so a track's findings aren't in tick order yet
+        track.list.sort_by_key(|f| f.tick);
+        findings.append(&mut track.list);
+    }
+
+    Ok(ValidationReport {
+        path: match zip_entry {
+            Some(entry) => format!("{} ({})", path, entry),
+            None => path.to_string()
+        },
+        track_count: mid.trk_count as usize,
+        findings,
+        counts,
+        file_warnings: mid.warnings
+    })
+}
+
+// loads the midi leniently and writes it back out. that's enough to fix everything validate
+// looks for: notes get paired up and closed, stray note offs and broken events are gone,
+// and every track gets an end-of-track. overlapping notes stay, they're not always a mistake
+pub fn write_repaired(path: &str, zip_entry: Option<&str>, out_path: &str, progress: Arc<LoadProgress>) -> Result<ExportStats, MidiLoadError> {
+    let mut mid: MIDIFile = MIDIFile::open(path, zip_entry, true, ParseMode::Lenient, NotePairing::Lifo, progress)?;
+    mid.parse_tracks()?;
+
+    let mut midi_evs: Vec<MIDIEvent> = Vec::new();
+    let mut notes: Vec<Vec<Note>> = Vec::new();
+    let mut tempo_evs: Vec<TempoEvent> = Vec::new();
+    let mut meta_evs: Vec<MetaEvent> = Vec::new();
+    mid.get_sequences(&mut midi_evs, &mut notes, &mut tempo_evs, &mut meta_evs, NoteFilter::default())?;
+
+    // signatures with the wrong length would only be written back wrong
+    meta_evs.retain(|ev| match ev.meta_name {
+        MetaEventName::TimeSignature => ev.data.len() == 4,
+        MetaEventName::KeySignature => ev.data.len() == 2,
+        _ => true
+    });
+
+    let export = MIDIExport {
+        division: mid.division,
+        tick_based: true,
+        transpose: 0,
+        tempo_evs: &tempo_evs,
+        midi_evs: &midi_evs,
+        meta_evs: &meta_evs,
+        notes: &notes
+    };
+    let format = if mid.format == 0 { SMFFormat::SingleTrack } else { SMFFormat::MultiTrack };
+    Ok(export.save(out_path, format)?)
+}
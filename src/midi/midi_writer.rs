@@ -0,0 +1,586 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use rayon::prelude::*;
+
+use crate::midi::midi_file::TimeDivision;
+use crate::midi::midi_track_parser::{self, MIDIEvent, MIDIEventType, MetaEvent, MetaEventName, Note, TempoEvent};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SMFFormat {
+    // everything in one track. notes from different tracks on the same key and channel can
+    // pair up differently when it's read back
+    SingleTrack = 0,
+    // the tracks the midi was loaded with, tempo changes go in the first one
+    MultiTrack = 1
+}
+
+// a loaded midi the way the player has it. notes come from the note lists, so transpose and
+// the load filters apply to them. the synth's events only give the controllers, program changes
+// and such, their note ons and offs are left out
+pub struct MIDIExport<'a> {
+    pub division: TimeDivision,
+    // notes are in ticks instead of microseconds
+    pub tick_based: bool,
+    pub transpose: i32,
+    pub tempo_evs: &'a [TempoEvent],
+    pub midi_evs: &'a [MIDIEvent],
+    pub meta_evs: &'a [MetaEvent],
+    // indexed by 255 - key, same as everywhere else
+    pub notes: &'a [Vec<Note>]
+}
+
+pub struct ExportStats {
+    pub track_count: usize,
+    pub notes_written: u64,
+    // pushed past 0-127 by transpose, or never in it to begin with
+    pub notes_skipped: u64
+}
+
+// what goes first when events share a tick. note offs go before note ons so a note that
+// starts where another one ends on the same key doesn't get ended right away
+const ORDER_META: u8 = 0;
+const ORDER_NOTE_OFF: u8 = 1;
+const ORDER_CHANNEL: u8 = 2;
+const ORDER_NOTE_ON: u8 = 3;
+
+#[derive(Clone, Copy)]
+enum Body {
+    Channel([u8; 3]),
+    Tempo(u32),
+    // index into meta_evs
+    Meta(u32)
+}
+
+#[derive(Clone, Copy)]
+struct OutEvent {
+    tick: u64,
+    order: u8,
+    port: u8,
+    body: Body
+}
+
+impl MIDIExport<'_> {
+    pub fn save(&self, path: &str, format: SMFFormat) -> io::Result<ExportStats> {
+        let mut w = BufWriter::new(File::create(path)?);
+        let stats = self.write(&mut w, format)?;
+        w.flush()?;
+        Ok(stats)
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W, format: SMFFormat) -> io::Result<ExportStats> {
+        let (tracks, notes_written, notes_skipped) = self.collect_events(format);
+        if tracks.len() > u16::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "too many tracks for a midi header"));
+        }
+
+        let track_count = tracks.len();
+        let chunks = tracks.into_par_iter().map(|evs| self.encode_track(evs)).collect::<Vec<_>>();
+
+        w.write_all(b"MThd")?;
+        w.write_all(&6u32.to_be_bytes())?;
+        w.write_all(&(format as u16).to_be_bytes())?;
+        w.write_all(&(track_count as u16).to_be_bytes())?;
+        w.write_all(&self.division.to_raw().to_be_bytes())?;
+        for chunk in chunks {
+            let len = u32::try_from(chunk.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "track is too big for a midi file"))?;
+            w.write_all(b"MTrk")?;
+            w.write_all(&len.to_be_bytes())?;
+            w.write_all(&chunk)?;
+        }
+
+        Ok(ExportStats { track_count, notes_written, notes_skipped })
+    }
+
+    fn to_ticks(&self, seconds: f64) -> u64 {
+        midi_track_parser::seconds_to_ticks(self.tempo_evs, self.division, seconds)
+    }
+
+    fn note_ticks(&self, time: u64) -> u64 {
+        if self.tick_based {
+            time
+        } else {
+            self.to_ticks(time as f64 / 1000000.0)
+        }
+    }
+
+    // sorts everything into the tracks it'll be written to, unordered for now
+    fn collect_events(&self, format: SMFFormat) -> (Vec<Vec<OutEvent>>, u64, u64) {
+        let track_count = match format {
+            SMFFormat::SingleTrack => 1,
+            SMFFormat::MultiTrack => {
+                let last_note = self.notes.iter().flatten().map(|n| n.track()).max();
+                let last_ev = self.midi_evs.iter().map(|e| e.track as usize).max();
+                let last_meta = self.meta_evs.iter().map(|e| e.track as usize).max();
+                last_note.max(last_ev).max(last_meta).map_or(1, |t| t + 1)
+            }
+        };
+        let track_of = |track: usize| if format == SMFFormat::MultiTrack { track } else { 0 };
+        let mut tracks: Vec<Vec<OutEvent>> = (0..track_count).map(|_| Vec::new()).collect();
+
+        for t in self.tempo_evs.iter() {
+            tracks[0].push(OutEvent { tick: t.time, order: ORDER_META, port: 0, body: Body::Tempo(t.tempo) });
+        }
+        // sysex goes where its marker is in the synth's events instead, a reset and the
+        // program changes around it have to stay in the same order
+        let mut sysex: BTreeMap<u16, VecDeque<usize>> = BTreeMap::new();
+        for (i, ev) in self.meta_evs.iter().enumerate() {
+            if matches!(ev.meta_name, MetaEventName::SysEx | MetaEventName::SysExEscape) {
+                sysex.entry(ev.track).or_default().push_back(i);
+                continue;
+            }
+            tracks[track_of(ev.track as usize)].push(OutEvent {
+                tick: self.to_ticks(ev.time),
+                order: ORDER_META,
+                port: 0,
+                body: Body::Meta(i as u32)
+            });
+        }
+
+        for ev in self.midi_evs.iter() {
+            let mut data = ev.data;
+            match ev.kind() {
+                // the notes get written from the note lists
+                MIDIEventType::NoteOn | MIDIEventType::NoteOff => continue,
+                // escapes don't get a marker, they go along with the next message that does
+                MIDIEventType::SysEx => {
+                    let queue = sysex.entry(ev.track).or_default();
+                    while let Some(i) = queue.pop_front() {
+                        tracks[track_of(ev.track as usize)].push(self.sysex_event(i));
+                        if self.meta_evs[i].meta_name == MetaEventName::SysEx {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                MIDIEventType::PolyAftertouch => {
+                    match Self::shift_key(data[0] as i32, self.transpose) {
+                        Some(key) => data[0] = key,
+                        None => continue
+                    }
+                }
+                _ => {}
+            }
+            // a data byte with the top bit set would be read back as a status byte
+            tracks[track_of(ev.track as usize)].push(OutEvent {
+                tick: self.to_ticks(ev.time),
+                order: ORDER_CHANNEL,
+                port: ev.port,
+                body: Body::Channel([ev.status, data[0].min(0x7F), data[1].min(0x7F)])
+            });
+        }
+
+        for (track, queue) in sysex {
+            for i in queue {
+                tracks[track_of(track as usize)].push(self.sysex_event(i));
+            }
+        }
+
+        let mut written = 0;
+        let mut skipped = 0;
+        for (i, key_notes) in self.notes.iter().enumerate() {
+            let key = match Self::shift_key(255 - i as i32, self.transpose) {
+                Some(key) => key,
+                None => {
+                    skipped += key_notes.len() as u64;
+                    continue;
+                }
+            };
+            for n in key_notes.iter() {
+                let start = self.note_ticks(n.start);
+                let port = n.channel() / 16;
+                let ch = n.channel() % 16;
+                let evs = &mut tracks[track_of(n.track())];
+                evs.push(OutEvent {
+                    tick: start,
+                    order: ORDER_NOTE_ON,
+                    port,
+                    body: Body::Channel([0x90 | ch, key, n.velocity().clamp(1, 0x7F)])
+                });
+                // notes that never ended stay that way
+                if n.end() != u64::MAX {
+                    let end = self.note_ticks(n.end());
+                    // a note with no length has to be ended right after its own note on
+                    let order = if end == start { ORDER_NOTE_ON } else { ORDER_NOTE_OFF };
+                    evs.push(OutEvent { tick: end, order, port, body: Body::Channel([0x80 | ch, key, 0x40]) });
+                }
+                written += 1;
+            }
+        }
+
+        (tracks, written, skipped)
+    }
+
+    fn sysex_event(&self, i: usize) -> OutEvent {
+        OutEvent { tick: self.to_ticks(self.meta_evs[i].time), order: ORDER_CHANNEL, port: 0, body: Body::Meta(i as u32) }
+    }
+
+    fn shift_key(key: i32, transpose: i32) -> Option<u8> {
+        let key = key + transpose;
+        if (0..=127).contains(&key) { Some(key as u8) } else { None }
+    }
+
+    fn encode_track(&self, mut evs: Vec<OutEvent>) -> Vec<u8> {
+        // stable, so everything on the same tick and order stays the way it was collected
+        evs.par_sort_by_key(|e| (e.tick, e.order));
+
+        let mut buf: Vec<u8> = Vec::with_capacity(evs.len() * 4 + 4);
+        let mut last_tick = 0;
+        // the parser keeps meta and sysex status bytes as the running status, so it only
+        // gets used between channel events
+        let mut running: u8 = 0;
+        let mut port: u8 = 0;
+        for ev in evs.iter() {
+            write_vlq(&mut buf, ev.tick - last_tick);
+            last_tick = ev.tick;
+
+            match ev.body {
+                Body::Channel([status, d1, d2]) => {
+                    // the port meta event takes this event's delta, the event itself comes right after
+                    if ev.port != port {
+                        buf.extend_from_slice(&[0xFF, 0x21, 0x01, ev.port, 0x00]);
+                        port = ev.port;
+                        running = 0;
+                    }
+                    if status != running {
+                        buf.push(status);
+                        running = status;
+                    }
+                    buf.push(d1);
+                    if !matches!(status & 0xF0, 0xC0 | 0xD0) {
+                        buf.push(d2);
+                    }
+                }
+                Body::Tempo(tempo) => {
+                    buf.extend_from_slice(&[0xFF, 0x51, 0x03]);
+                    buf.extend_from_slice(&tempo.to_be_bytes()[1..]);
+                    running = 0;
+                }
+                Body::Meta(i) => {
+                    let meta = &self.meta_evs[i as usize];
+                    match meta.meta_name {
+                        MetaEventName::SysEx => buf.push(0xF0),
+                        MetaEventName::SysExEscape => buf.push(0xF7),
+                        name => buf.extend_from_slice(&[0xFF, name as u8])
+                    }
+                    write_vlq(&mut buf, meta.data.len() as u64);
+                    buf.extend_from_slice(&meta.data);
+                    running = 0;
+                }
+            }
+        }
+        buf.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+        buf
+    }
+}
+
+fn write_vlq(buf: &mut Vec<u8>, mut n: u64) {
+    let mut bytes = [0u8; 10];
+    let mut i = bytes.len() - 1;
+    bytes[i] = (n & 0x7F) as u8;
+    n >>= 7;
+    while n > 0 {
+        i -= 1;
+        bytes[i] = (n & 0x7F) as u8 | 0x80;
+        n >>= 7;
+    }
+    buf.extend_from_slice(&bytes[i..]);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::midi::load_progress::LoadProgress;
+    use crate::midi::midi_file::MIDIFile;
+    use crate::midi::midi_track_parser::{NotePairing, ParseMode};
+    use crate::midi::note_filter::NoteFilter;
+
+    const PPQ_96: u16 = 96;
+
+    struct Loaded {
+        division: TimeDivision,
+        tick_based: bool,
+        midi_evs: Vec<MIDIEvent>,
+        notes: Vec<Vec<Note>>,
+        tempo_evs: Vec<TempoEvent>,
+        meta_evs: Vec<MetaEvent>
+    }
+
+    impl Loaded {
+        fn export(&self, format: SMFFormat, transpose: i32) -> (Vec<u8>, ExportStats) {
+            let export = MIDIExport {
+                division: self.division,
+                tick_based: self.tick_based,
+                transpose,
+                tempo_evs: &self.tempo_evs,
+                midi_evs: &self.midi_evs,
+                meta_evs: &self.meta_evs,
+                notes: &self.notes
+            };
+            let mut buf = Vec::new();
+            let stats = export.write(&mut buf, format).unwrap();
+            (buf, stats)
+        }
+
+        // everything the synth gets that isn't a note
+        fn channel_evs(&self) -> Vec<(f64, u8, [u8; 2], u8, u16)> {
+            self.midi_evs.iter()
+                .filter(|e| !matches!(e.kind(), MIDIEventType::NoteOn | MIDIEventType::NoteOff))
+                .map(|e| (e.time, e.status, e.data, e.port, e.track))
+                .collect()
+        }
+
+        fn tempos(&self) -> Vec<(u64, u32)> {
+            self.tempo_evs.iter().map(|t| (t.time, t.tempo)).collect()
+        }
+
+        fn note_count(&self) -> usize {
+            self.notes.iter().map(|k| k.len()).sum()
+        }
+    }
+
+    fn load(bytes: Vec<u8>, pairing: NotePairing) -> Loaded {
+        load_as(bytes, true, pairing)
+    }
+
+    fn load_as(bytes: Vec<u8>, tick_based: bool, pairing: NotePairing) -> Loaded {
+        let mut mid = MIDIFile::from_reader(Cursor::new(bytes), tick_based, ParseMode::Strict, pairing, Arc::new(LoadProgress::new())).unwrap();
+        let mut loaded = Loaded {
+            division: mid.division,
+            tick_based,
+            midi_evs: Vec::new(),
+            notes: Vec::new(),
+            tempo_evs: Vec::new(),
+            meta_evs: Vec::new()
+        };
+        mid.get_sequences(&mut loaded.midi_evs, &mut loaded.notes, &mut loaded.tempo_evs, &mut loaded.meta_evs, NoteFilter::default()).unwrap();
+        loaded
+    }
+
+    // events are (delta, bytes), the end of track gets added
+    fn track(evs: &[(u64, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (delta, ev) in evs {
+            write_vlq(&mut data, *delta);
+            data.extend_from_slice(ev);
+        }
+        data.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+        let mut chunk = b"MTrk".to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        chunk.extend(data);
+        chunk
+    }
+
+    fn smf(format: u16, tracks: &[Vec<u8>]) -> Vec<u8> {
+        smf_with(format, PPQ_96, tracks)
+    }
+
+    fn smf_with(format: u16, division: u16, tracks: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = b"MThd".to_vec();
+        buf.extend_from_slice(&6u32.to_be_bytes());
+        buf.extend_from_slice(&format.to_be_bytes());
+        buf.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&division.to_be_bytes());
+        for t in tracks {
+            buf.extend_from_slice(t);
+        }
+        buf
+    }
+
+    fn assert_round_trip(bytes: Vec<u8>, pairing: NotePairing, format: SMFFormat) -> Loaded {
+        let before = load(bytes, pairing);
+        let (written, stats) = before.export(format, 0);
+        let after = load(written, pairing);
+
+        assert_eq!(stats.notes_written as usize, before.note_count());
+        assert_eq!(stats.notes_skipped, 0);
+        assert_eq!(after.notes, before.notes);
+        assert_eq!(after.channel_evs(), before.channel_evs());
+        assert_eq!(after.tempos(), before.tempos());
+        before
+    }
+
+    #[test]
+    fn format_1_round_trip() {
+        let conductor = track(&[
+            (0, &[0xFF, 0x03, 0x04, b'l', b'e', b'a', b'd']),
+            (0, &[0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20]),
+            (192, &[0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90])
+        ]);
+        let piano = track(&[
+            (0, &[0xC0, 0x05]),
+            (0, &[0x90, 60, 100]),
+            (0, &[0x91, 64, 80]),
+            (48, &[0xB0, 7, 90]),
+            (48, &[0x80, 60, 0]),
+            (0, &[0x81, 64, 0]),
+            (96, &[0x90, 62, 70]),
+            (300, &[0x90, 62, 0])
+        ]);
+        let drums = track(&[
+            (24, &[0xFF, 0x21, 0x01, 0x01]),
+            (0, &[0x99, 36, 127]),
+            (24, &[0x89, 36, 64]),
+            (24, &[0xE9, 0x00, 0x50])
+        ]);
+
+        let loaded = assert_round_trip(smf(1, &[conductor, piano, drums]), NotePairing::Lifo, SMFFormat::MultiTrack);
+        assert_eq!(loaded.note_count(), 4);
+    }
+
+    #[test]
+    fn format_0_round_trip() {
+        let only = track(&[
+            (0, &[0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20]),
+            (0, &[0xC0, 0x10]),
+            (0, &[0x90, 60, 100]),
+            (10, &[0x92, 67, 90]),
+            (86, &[0x80, 60, 0]),
+            (0, &[0x90, 72, 30]),
+            (96, &[0x82, 67, 0]),
+            (0, &[0x80, 72, 0]),
+            (0, &[0xD2, 0x40])
+        ]);
+
+        let loaded = assert_round_trip(smf(0, &[only]), NotePairing::Lifo, SMFFormat::SingleTrack);
+        assert_eq!(loaded.note_count(), 3);
+    }
+
+    #[test]
+    fn transpose_shifts_keys_and_skips_what_falls_off() {
+        let t = track(&[
+            (0, &[0x90, 60, 100]),
+            (0, &[0x90, 126, 100]),
+            (0, &[0xA0, 60, 20]),
+            (96, &[0x80, 60, 0]),
+            (0, &[0x80, 126, 0])
+        ]);
+        let before = load(smf(0, &[t]), NotePairing::Lifo);
+        let (written, stats) = before.export(SMFFormat::SingleTrack, 2);
+        let after = load(written, NotePairing::Lifo);
+
+        assert_eq!(stats.notes_written, 1);
+        assert_eq!(stats.notes_skipped, 1);
+        assert_eq!(after.note_count(), 1);
+        assert_eq!(after.notes[255 - 62], before.notes[255 - 60]);
+        let aftertouch = after.midi_evs.iter().find(|e| e.kind() == MIDIEventType::PolyAftertouch).unwrap();
+        assert_eq!(aftertouch.data, [62, 20]);
+    }
+
+    #[test]
+    fn overlapping_notes_keep_their_pairing() {
+        let t = track(&[
+            (0, &[0x90, 60, 100]),
+            (10, &[0x90, 60, 50]),
+            (10, &[0x90, 60, 70]),
+            (10, &[0x80, 60, 0]),
+            (20, &[0x80, 60, 0]),
+            (5, &[0x90, 60, 90]),
+            (5, &[0x80, 60, 0]),
+            (40, &[0x80, 60, 0])
+        ]);
+
+        for pairing in [NotePairing::Lifo, NotePairing::Fifo, NotePairing::TruncatePrevious] {
+            let loaded = assert_round_trip(smf(0, std::slice::from_ref(&t)), pairing, SMFFormat::SingleTrack);
+            assert_eq!(loaded.note_count(), 4, "{:?}", pairing);
+        }
+    }
+
+    #[test]
+    fn zero_length_notes_keep_their_pairing() {
+        let t = track(&[
+            // nothing else playing
+            (0, &[0x90, 60, 100]),
+            (0, &[0x80, 60, 0]),
+            // two notes start together and one of them ends right away
+            (10, &[0x90, 60, 80]),
+            (0, &[0x90, 60, 40]),
+            (0, &[0x80, 60, 0]),
+            // one ends where the next one starts and ends
+            (10, &[0x80, 60, 0]),
+            (0, &[0x90, 60, 20]),
+            (0, &[0x90, 60, 0]),
+            // and one in the middle of a longer note
+            (10, &[0x90, 60, 90]),
+            (10, &[0x90, 60, 60]),
+            (0, &[0x80, 60, 0]),
+            (10, &[0x80, 60, 0])
+        ]);
+
+        for pairing in [NotePairing::Lifo, NotePairing::Fifo, NotePairing::TruncatePrevious] {
+            let loaded = assert_round_trip(smf(0, std::slice::from_ref(&t)), pairing, SMFFormat::SingleTrack);
+            let zero_length = loaded.notes[255 - 60].iter().filter(|n| n.end() == n.start).count();
+            assert!(zero_length >= 2, "{:?} only has {} zero length notes", pairing, zero_length);
+        }
+    }
+
+    // loaded in time mode the notes are in microseconds, they have to go back to the ticks they
+    // were read from. checked against the same file loaded in tick mode
+    fn assert_time_based_round_trip(bytes: Vec<u8>, format: SMFFormat) -> Loaded {
+        let in_ticks = load_as(bytes.clone(), true, NotePairing::Lifo);
+        let before = load_as(bytes, false, NotePairing::Lifo);
+        let (written, stats) = before.export(format, 0);
+        let after = load_as(written, true, NotePairing::Lifo);
+
+        assert_eq!(stats.notes_written as usize, in_ticks.note_count());
+        assert_eq!(after.division, in_ticks.division);
+        assert_eq!(after.notes, in_ticks.notes);
+        assert_eq!(after.tempos(), in_ticks.tempos());
+        before
+    }
+
+    #[test]
+    fn time_based_round_trip_across_a_tempo_change() {
+        let conductor = track(&[
+            (0, &[0xFF, 0x51, 0x03, 0x07, 0xA1, 0x21]),
+            // about 139.92 bpm from here on, ticks don't land on whole microseconds
+            (500, &[0xFF, 0x51, 0x03, 0x06, 0x8B, 0x1D]),
+            (1000, &[0xFF, 0x51, 0x03, 0x0F, 0x42, 0x3F])
+        ]);
+        let notes = track(&[
+            (1, &[0x90, 60, 100]),
+            (0, &[0x91, 64, 80]),
+            (123, &[0x80, 60, 0]),
+            // ends on the tempo change's tick and the next one starts there
+            (376, &[0x81, 64, 0]),
+            (0, &[0x90, 62, 70]),
+            (1, &[0x90, 67, 60]),
+            (997, &[0x80, 62, 0]),
+            (1, &[0x80, 67, 0]),
+            (4321, &[0x90, 72, 90]),
+            (12345, &[0x80, 72, 0])
+        ]);
+
+        let loaded = assert_time_based_round_trip(smf(1, &[conductor, notes]), SMFFormat::MultiTrack);
+        assert_eq!(loaded.note_count(), 5);
+        // the notes really were in microseconds
+        assert_eq!(loaded.notes[255 - 60][0].start, 5208);
+    }
+
+    #[test]
+    fn time_based_round_trip_with_smpte_division() {
+        // 29.97 drop-frame at 40 ticks a frame, so a tick is about 834.17 microseconds.
+        // the tempo change doesn't apply but has to stay where it is
+        let division = 0xE328;
+        let only = track(&[
+            (0, &[0x90, 60, 100]),
+            (1, &[0x90, 61, 90]),
+            (7, &[0x80, 60, 0]),
+            (300, &[0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90]),
+            (1, &[0x80, 61, 0]),
+            (1, &[0x90, 62, 80]),
+            (9999, &[0x80, 62, 0])
+        ]);
+
+        let loaded = assert_time_based_round_trip(smf_with(0, division, &[only]), SMFFormat::SingleTrack);
+        assert_eq!(loaded.division, TimeDivision::SMPTE { fps: 29, ticks_per_frame: 40 });
+        assert_eq!(loaded.note_count(), 3);
+        assert_eq!(loaded.notes[255 - 61][0].start, 834);
+    }
+}
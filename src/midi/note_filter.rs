@@ -0,0 +1,67 @@
+use crate::midi::midi_track_parser::Note;
+
+// optional clean up of the notes at load time, for black midis that are mostly notes nobody
+// will ever see or hear. only the notes that get drawn are touched, the synth still gets every event
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct NoteFilter {
+    // notes on the same key and channel that start and end together with one that's already kept
+    pub remove_duplicates: bool,
+    // in ticks, 0 keeps everything
+    pub min_length: u32,
+    pub min_velocity: u8
+}
+
+impl NoteFilter {
+    // notes of a single track, still in ticks. returns how many were removed
+    pub fn filter_track_notes(&self, notes: &mut Vec<Note>) -> usize {
+        if self.min_length == 0 && self.min_velocity == 0 {
+            return 0;
+        }
+        let before = notes.len();
+        // notes that never ended don't have a length or velocity yet
+        notes.retain(|n| n.end() == u64::MAX
+            || (n.velocity() >= self.min_velocity && n.end() - n.start >= self.min_length as u64));
+        before - notes.len()
+    }
+
+    // one key's notes once every track is merged in, sorted by start. returns how many were removed
+    pub fn filter_merged_notes(&self, notes: &mut Vec<Note>) -> usize {
+        if !self.remove_duplicates {
+            return 0;
+        }
+        let before = notes.len();
+
+        // compacts in place. sorting a group of notes that start together puts its duplicates
+        // next to each other. only the indices get sorted, the notes keep their order, and the
+        // sort is stable so the first one in track order is the one kept
+        let mut kept = 0;
+        let mut group = 0;
+        let mut order: Vec<usize> = Vec::new();
+        let mut duplicate: Vec<bool> = Vec::new();
+        while group < notes.len() {
+            let start = notes[group].start;
+            let group_end = group + notes[group..].partition_point(|n| n.start == start);
+
+            order.clear();
+            order.extend(group..group_end);
+            order.sort_by_key(|i| (notes[*i].channel(), notes[*i].end()));
+            duplicate.clear();
+            duplicate.resize(group_end - group, false);
+            for pair in order.windows(2) {
+                let (a, b) = (notes[pair[0]], notes[pair[1]]);
+                duplicate[pair[1] - group] = a.channel() == b.channel() && a.end() == b.end();
+            }
+
+            for i in group..group_end {
+                if !duplicate[i - group] {
+                    notes[kept] = notes[i];
+                    kept += 1;
+                }
+            }
+            group = group_end;
+        }
+        notes.truncate(kept);
+
+        before - notes.len()
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::midi::midi_track_parser::{MIDIEvent, MIDIEventType};
+
+// xsynth lets go of the oldest voice on a key for every note off, no matter what the pairing
+// setting says, and it doesn't know about tracks either. it can't be told which voice to end,
+// so this only makes sure it ends one whenever a track's note list ends a note:
+// note offs with nothing open in their own track are dropped instead of ending another track's
+// note, and every note off carries the velocity of the voice the synth is going to let go of,
+// so velocity skipping leaves out the note off of every note on it left out.
+// the same number of voices play as notes are drawn, but with notes of different velocities
+// stacked on a key, the one left playing can be a different one than the note lists say
+#[derive(Default)]
+pub struct SynthPairing {
+    // velocities of the voices on key * 256 + channel (counting ports), oldest first
+    playing: Vec<VecDeque<u8>>,
+    // how many notes are open on a key and channel in every track
+    open: HashMap<(u16, u16), u32>
+}
+
+impl SynthPairing {
+    pub fn new() -> Self {
+        Self {
+            playing: Vec::new(),
+            open: HashMap::new()
+        }
+    }
+
+    // evs are everything the synth gets, merged and in order. when streaming every window has to
+    // go through the same SynthPairing. nothing is ever added, only stray note offs are taken out
+    pub fn pair(&mut self, evs: &mut Vec<MIDIEvent>) {
+        if self.playing.is_empty() {
+            self.playing = (0..256*256).map(|_| VecDeque::new()).collect();
+        }
+
+        let mut kept = 0;
+        for i in 0..evs.len() {
+            let mut ev = evs[i];
+            let slot = ev.data[0] as u16 * 256 + ev.full_channel() as u16;
+            match ev.kind() {
+                MIDIEventType::NoteOn => {
+                    self.playing[slot as usize].push_back(ev.data[1]);
+                    *self.open.entry((ev.track, slot)).or_default() += 1;
+                }
+                MIDIEventType::NoteOff => {
+                    // nothing playing in its own track, so the synth mustn't end a note from another one
+                    match self.open.get_mut(&(ev.track, slot)) {
+                        Some(open) if *open > 0 => *open -= 1,
+                        _ => continue
+                    }
+                    // every open note has a voice, so there's always one to let go of
+                    ev.data[1] = self.playing[slot as usize].pop_front().unwrap();
+                }
+                _ => {}
+            }
+            evs[kept] = ev;
+            kept += 1;
+        }
+        evs.truncate(kept);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn on(time: f64, track: u16, key: u8, vel: u8) -> MIDIEvent {
+        MIDIEvent::new(time, track, 0, 0x90, key, vel)
+    }
+
+    fn off(time: f64, track: u16, key: u8) -> MIDIEvent {
+        MIDIEvent::new(time, track, 0, 0x80, key, 0)
+    }
+
+    #[test]
+    fn note_offs_carry_the_oldest_voices_velocity() {
+        let mut evs = vec![
+            on(0.0, 0, 60, 100),
+            on(0.1, 0, 60, 50),
+            on(0.2, 0, 60, 70),
+            off(0.3, 0, 60),
+            off(0.4, 0, 60),
+            on(0.5, 0, 60, 90),
+            off(0.6, 0, 60),
+            off(0.7, 0, 60)
+        ];
+        SynthPairing::new().pair(&mut evs);
+
+        let offs: Vec<u8> = evs.iter().filter(|e| e.kind() == MIDIEventType::NoteOff).map(|e| e.data[1]).collect();
+        assert_eq!(offs, vec![100, 50, 70, 90]);
+        assert_eq!(evs.len(), 8);
+    }
+
+    #[test]
+    fn stray_note_offs_are_dropped() {
+        let mut evs = vec![
+            on(0.0, 0, 60, 100),
+            // track 1 never started a note on this key
+            off(0.1, 1, 60),
+            // and this one is on another channel
+            MIDIEvent::new(0.2, 0, 0, 0x81, 60, 0),
+            off(0.3, 0, 60),
+            off(0.4, 0, 60)
+        ];
+        SynthPairing::new().pair(&mut evs);
+
+        let kept: Vec<(f64, u8, u8)> = evs.iter().map(|e| (e.time, e.status, e.data[1])).collect();
+        assert_eq!(kept, vec![(0.0, 0x90, 100), (0.3, 0x80, 100)]);
+    }
+
+    #[test]
+    fn ports_are_their_own_channels() {
+        let mut evs = vec![
+            on(0.0, 0, 60, 100),
+            MIDIEvent::new(0.1, 0, 1, 0x90, 60, 40),
+            MIDIEvent::new(0.2, 0, 1, 0x80, 60, 0),
+            off(0.3, 0, 60)
+        ];
+        SynthPairing::new().pair(&mut evs);
+
+        let offs: Vec<(u8, u8)> = evs.iter().filter(|e| e.kind() == MIDIEventType::NoteOff).map(|e| (e.port, e.data[1])).collect();
+        assert_eq!(offs, vec![(1, 40), (0, 100)]);
+    }
+
+    // streamed midis go through in windows, notes can start in one and end in another
+    #[test]
+    fn windows_carry_over() {
+        let mut pairing = SynthPairing::new();
+        let mut first = vec![on(0.0, 0, 60, 100), on(0.1, 0, 60, 50)];
+        let mut second = vec![off(0.2, 0, 60), off(0.3, 0, 60), off(0.4, 0, 60)];
+        pairing.pair(&mut first);
+        pairing.pair(&mut second);
+
+        let offs: Vec<u8> = second.iter().map(|e| e.data[1]).collect();
+        assert_eq!(offs, vec![100, 50]);
+    }
+}
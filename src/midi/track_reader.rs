@@ -0,0 +1,65 @@
+use std::fs::File;
+use std::io::{Read, Seek};
+
+use crate::midi::buffered_byte_reader::BufferedByteReader;
+use crate::midi::mapped_byte_reader::MappedByteReader;
+use crate::midi::midi_error::MidiLoadError;
+
+// what a MIDITrack reads its bytes from. mapped when the file could be mmapped,
+// buffered reads through the shared stream otherwise
+pub enum TrackReader<R = File> {
+    Buffered(BufferedByteReader<R>),
+    Mapped(MappedByteReader)
+}
+
+impl<R: Read + Seek> TrackReader<R> {
+    pub fn pos(&self) -> usize {
+        match self {
+            TrackReader::Buffered(r) => r.pos,
+            TrackReader::Mapped(r) => r.pos
+        }
+    }
+
+    pub fn seek(&mut self, offset: isize, origin: i32) -> Result<(), MidiLoadError> {
+        match self {
+            TrackReader::Buffered(r) => r.seek(offset, origin),
+            TrackReader::Mapped(r) => r.seek(offset, origin)
+        }
+    }
+
+    pub fn read(&mut self, dst: &mut [u8], size: usize) -> Result<(), MidiLoadError> {
+        match self {
+            TrackReader::Buffered(r) => r.read(dst, size),
+            TrackReader::Mapped(r) => r.read(dst, size)
+        }
+    }
+
+    pub fn at_end(&self) -> bool {
+        match self {
+            TrackReader::Buffered(r) => r.at_end(),
+            TrackReader::Mapped(r) => r.at_end()
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        match self {
+            TrackReader::Buffered(r) => r.remaining(),
+            TrackReader::Mapped(r) => r.remaining()
+        }
+    }
+
+    #[inline]
+    pub fn read_byte(&mut self) -> Result<u8, MidiLoadError> {
+        match self {
+            TrackReader::Buffered(r) => r.read_byte(),
+            TrackReader::Mapped(r) => r.read_byte()
+        }
+    }
+
+    pub fn skip_bytes(&mut self, size: usize) -> Result<(), MidiLoadError> {
+        match self {
+            TrackReader::Buffered(r) => r.skip_bytes(size),
+            TrackReader::Mapped(r) => r.skip_bytes(size)
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::io::{Read, Seek};
+
+use crate::midi::midi_error::MidiLoadError;
+use crate::midi::midi_track_parser::MAX_PORTS;
+use crate::midi::track_reader::TrackReader;
+
+// tracks smaller than this are parsed in one go, splitting isn't worth the pre-scan
+const SPLIT_MIN_LEN: usize = 32 * 1024 * 1024;
+const MIN_CHUNK_LEN: usize = 4 * 1024 * 1024;
+
+// somewhere a chunk of a track can start parsing from: an event boundary, the absolute tick
+// of the event before it and the running status and port in effect there
+#[derive(Clone, Copy, Debug)]
+pub struct Checkpoint {
+    pub pos: usize,
+    pub tick: u64,
+    pub status: u8,
+    pub port: u8
+}
+
+// how many chunks a track of len bytes should be parsed in. a few more chunks than threads
+// so one slow chunk (lots of notes) doesn't leave the other cores waiting
+pub fn chunk_count(len: usize) -> usize {
+    let threads = rayon::current_num_threads();
+    if threads < 2 || len < SPLIT_MIN_LEN {
+        return 1;
+    }
+    (len / MIN_CHUNK_LEN).min(threads * 4).max(1)
+}
+
+// walks over the track without storing anything and drops a checkpoint roughly every len / chunks
+// bytes. stops early at the end of track, or when the data stops making sense; whatever is left
+// goes to the last chunk, which runs into (and reports) the same problem when it gets parsed.
+// has to step over events exactly the way MIDITrack::parse_ev does, otherwise the chunks go out of sync.
+// note_counts gets how many note ons every chunk has on each key, so the chunks' note lists
+// can be allocated once. a scan that stopped early leaves the rest of the notes uncounted
+pub fn find_checkpoints<R: Read + Seek>(rdr: &mut TrackReader<R>, len: usize, chunks: usize, note_counts: &mut Vec<Vec<usize>>) -> Vec<Checkpoint> {
+    let mut checkpoints = Vec::with_capacity(chunks.saturating_sub(1));
+    note_counts.clear();
+    note_counts.push(vec![0; 256]);
+    if chunks < 2 {
+        return checkpoints;
+    }
+
+    let every = len / chunks;
+    let mut next = rdr.pos() + every;
+    let mut tick: u64 = 0;
+    let mut status: u8 = 0x00;
+    let mut port: u8 = 0;
+
+    loop {
+        let pos = rdr.pos();
+        // the last chunk still gets scanned to count its notes
+        if pos >= next && checkpoints.len() < chunks - 1 {
+            checkpoints.push(Checkpoint { pos, tick, status, port });
+            note_counts.push(vec![0; 256]);
+            next = pos + every;
+        }
+
+        match skip_ev(rdr, &mut tick, &mut status, &mut port, note_counts.last_mut().unwrap()) {
+            Ok(true) => {},
+            _ => break
+        }
+    }
+
+    checkpoints
+}
+
+fn read_delta<R: Read + Seek>(rdr: &mut TrackReader<R>) -> Result<u64, MidiLoadError> {
+    let mut n: u64 = 0;
+    loop {
+        let b = rdr.read_byte()?;
+        n = (n << 7) | ((b & 0x7F) as u64);
+        if (b & 0x80) == 0x00 { break; }
+    }
+    Ok(n)
+}
+
+// false once the end of track event is hit
+fn skip_ev<R: Read + Seek>(rdr: &mut TrackReader<R>, tick: &mut u64, status: &mut u8, port: &mut u8, note_counts: &mut [usize]) -> Result<bool, MidiLoadError> {
+    *tick += read_delta(rdr)?;
+
+    let mut command = rdr.read_byte()?;
+    if command < 0x80 {
+        rdr.seek(-1, 1)?;
+        command = *status;
+    }
+    *status = command;
+
+    match command & 0xF0 {
+        0x90 => {
+            let key = rdr.read_byte()?;
+            if rdr.read_byte()? != 0 {
+                note_counts[key as usize] += 1;
+            }
+        }
+        0x80 | 0xA0 | 0xB0 | 0xE0 => rdr.skip_bytes(2)?,
+        0xC0 | 0xD0 => rdr.skip_bytes(1)?,
+        0xF0 => {
+            match command {
+                0xFF => {
+                    let meta = rdr.read_byte()?;
+                    let val = read_delta(rdr)? as usize;
+                    match meta {
+                        0x2F => return Ok(false),
+                        // the tempo is always read in full, even if the length says otherwise
+                        0x51 => rdr.skip_bytes(val.max(3))?,
+                        0x21 if val >= 1 => {
+                            *port = rdr.read_byte()?.min(MAX_PORTS - 1);
+                            rdr.skip_bytes(val - 1)?;
+                        }
+                        _ => rdr.skip_bytes(val)?
+                    }
+                }
+                0xF0 | 0xF7 => {
+                    let sysex_len = read_delta(rdr)?;
+                    rdr.skip_bytes(sysex_len as usize)?;
+                }
+                0xF2 => rdr.skip_bytes(2)?,
+                0xF3 => rdr.skip_bytes(1)?,
+                _ => {}
+            }
+        },
+        _ => {}
+    }
+    Ok(true)
+}
@@ -1,4 +1,5 @@
 pub mod window;
 pub mod shader;
 pub mod renderer;
-pub mod buffers;
\ No newline at end of file
+pub mod buffers;
+pub mod lyrics;
\ No newline at end of file
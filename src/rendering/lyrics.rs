@@ -0,0 +1,139 @@
+use crate::midi::midi_track_parser::{MetaEvent, MetaEventName};
+
+pub struct Syllable {
+    pub time: f64,
+    pub text: String
+}
+
+pub struct LyricLine {
+    pub syllables: Vec<Syllable>,
+    // a '\' started this line, so the one before it shouldn't lead into it
+    pub paragraph: bool
+}
+
+impl LyricLine {
+    fn start(&self) -> f64 {
+        self.syllables.first().map_or(f64::INFINITY, |s| s.time)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LyricSource {
+    // lyric (0x05) events
+    Lyric,
+    // .kar files put the lyrics in text events, with '@' headers in front
+    Karaoke
+}
+
+// the markers and lyrics of a midi, laid out in lines for the overlay.
+// meta events can be added a window at a time, so nothing here needs the whole midi up front
+pub struct Lyrics {
+    markers: Vec<(f64, String)>,
+    // only the last line can be empty, it's the one syllables go into
+    lines: Vec<LyricLine>,
+    // whichever kind of event shows up first wins, files that have both repeat the same lyrics
+    source: Option<LyricSource>,
+    // tracks that had '@' headers, those are the only ones with lyrics in a .kar
+    kar_tracks: Vec<u16>
+}
+
+impl Lyrics {
+    pub fn new() -> Self {
+        Self {
+            markers: Vec::new(),
+            lines: vec![LyricLine { syllables: Vec::new(), paragraph: false }],
+            source: None,
+            kar_tracks: Vec::new()
+        }
+    }
+
+    pub fn clear(&mut self) -> () {
+        *self = Self::new();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markers.is_empty() && self.lines[0].syllables.is_empty()
+    }
+
+    // has to be called in time order
+    pub fn add_events(&mut self, meta_evs: &[MetaEvent]) -> () {
+        for meta in meta_evs {
+            match meta.meta_name {
+                MetaEventName::Marker => {
+                    self.markers.push((meta.time, meta.text().trim().to_string()));
+                },
+                MetaEventName::Lyric => {
+                    if self.source.is_none() {
+                        self.source = Some(LyricSource::Lyric);
+                    }
+                    if self.source == Some(LyricSource::Lyric) {
+                        self.add_syllable(meta.time, &meta.text());
+                    }
+                },
+                MetaEventName::Text => {
+                    let text = meta.text();
+                    if let Some(header) = text.strip_prefix('@') {
+                        if self.source.is_none() && header.starts_with('K') {
+                            self.source = Some(LyricSource::Karaoke);
+                        }
+                        if !self.kar_tracks.contains(&meta.track) {
+                            self.kar_tracks.push(meta.track);
+                        }
+                    } else if self.source == Some(LyricSource::Karaoke) && self.kar_tracks.contains(&meta.track) {
+                        self.add_syllable(meta.time, &text);
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    // '\' in front starts a new paragraph and '/' a new line. lyric events end lines with a newline instead
+    fn add_syllable(&mut self, time: f64, text: &str) -> () {
+        let mut text = text;
+        if let Some(rest) = text.strip_prefix('\\') {
+            self.break_line(true);
+            text = rest;
+        } else if let Some(rest) = text.strip_prefix('/') {
+            self.break_line(false);
+            text = rest;
+        }
+
+        let ends_line = text.ends_with(['\r', '\n']);
+        let text = text.trim_end_matches(['\r', '\n']);
+        if !text.is_empty() {
+            self.lines.last_mut().unwrap().syllables.push(Syllable { time, text: text.to_string() });
+        }
+        if ends_line {
+            self.break_line(false);
+        }
+    }
+
+    fn break_line(&mut self, paragraph: bool) -> () {
+        let last = self.lines.last_mut().unwrap();
+        if last.syllables.is_empty() {
+            last.paragraph |= paragraph;
+        } else {
+            self.lines.push(LyricLine { syllables: Vec::new(), paragraph });
+        }
+    }
+
+    pub fn marker_at(&self, time: f64) -> Option<&str> {
+        let idx = self.markers.partition_point(|(t, _)| *t <= time);
+        if idx == 0 {
+            return None;
+        }
+        let text = self.markers[idx - 1].1.as_str();
+        if text.is_empty() { None } else { Some(text) }
+    }
+
+    // the line being sung and the one after it. before the first line starts, that one is the upcoming line
+    pub fn lines_at(&self, time: f64) -> (Option<&LyricLine>, Option<&LyricLine>) {
+        let idx = self.lines.partition_point(|line| line.start() <= time);
+        let next = self.lines.get(idx).filter(|line| !line.syllables.is_empty());
+        if idx == 0 {
+            return (None, next);
+        }
+        (Some(&self.lines[idx - 1]), next.filter(|line| !line.paragraph))
+    }
+}
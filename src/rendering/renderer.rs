@@ -3,7 +3,7 @@ use glutin::{window::Window, ContextWrapper, PossiblyCurrent};
 use itertools::Itertools;
 use core::str;
 use std::{fs::{create_dir, File}, io::{Read, Write}, path::absolute};
-use crate::{midi::midi_track_parser::{MetaEvent, MetaEventName, Note, TempoEvent}, rendering::{buffers::*, shader::*}, set_attribute};
+use crate::{midi::{midi_file::TimeDivision, midi_track_parser::{Note, TempoEvent}}, rendering::{buffers::*, shader::*}, set_attribute};
 
 // random color!!!
 use rand::prelude::*;
@@ -109,7 +109,7 @@ pub struct Renderer {
     pub notes_render: Vec<RenderNote>,
     pub note_color_table: Vec<u32>,
     pub note_color_index_table: Vec<usize>,
-    pub time: f32,
+    pub time: f64,
     pub time_changed: bool,
     last_note_starts: [usize; 257],
 
@@ -143,10 +143,7 @@ pub struct Renderer {
 
     // meta events
     pub tempo_events: Vec<TempoEvent>,
-    pub ppq: u16,
-    pub meta_events: Vec<MetaEvent>,
-    pub meta_passed: usize,
-    pub curr_marker_text: String
+    pub division: TimeDivision
 }
 
 impl Renderer {
@@ -441,18 +438,8 @@ impl Renderer {
             background_color: [0.5, 0.5, 0.5],
             notes_transpose: 0,
 
-            curr_marker_text: String::new(),
-            meta_events: vec![
-                MetaEvent {
-                    time: 1.0,
-                    meta_name: MetaEventName::Marker,
-                    data: String::from("You should be able to see this if this works!").as_mut_vec().to_vec()
-                }
-            ],
-            meta_passed: 0,
-
             tempo_events: Vec::new(),
-            ppq: 960,
+            division: TimeDivision::PPQ(960),
             tick_based: true,
         }
     }
@@ -462,6 +449,13 @@ impl Renderer {
         self.render_notes = notes;
     }
 
+    // for midis that are streamed in. every key's new notes start after the ones it already has
+    pub fn append_notes(&mut self, notes: Vec<Vec<Note>>) -> () {
+        for (key_notes, mut new_notes) in self.render_notes.iter_mut().zip(notes) {
+            key_notes.append(&mut new_notes);
+        }
+    }
+
     pub fn set_colors(&mut self, colors: Vec<u32>) -> () {
         self.note_color_table = colors;
         self.note_color_index_table = (0..self.note_color_table.len()).collect_vec();
@@ -474,37 +468,29 @@ impl Renderer {
         }
     }
 
-    fn update_meta_info(&mut self) -> () {
-        for meta in self.meta_events.iter() {
-            if meta.time > self.time { break; }
-            match meta.meta_name {
-                MetaEventName::Marker => {
-                    self.curr_marker_text = String::from_utf8_lossy(&meta.data).to_string();
-                },
-                _ => {
-
-                }
-            }
-        }
-    }
-
     pub unsafe fn resize(&mut self, width: i32, height: i32) -> () {
         gl::Viewport(0, 0, width, height);
         self.width = width as f32;
         self.height = height as f32;
     }
 
-    fn get_time(&mut self) -> f32 {
+    fn get_time(&mut self) -> f64 {
         if !self.tick_based {
             return self.time;
         }
 
+        let ppq = match self.division {
+            TimeDivision::PPQ(ppq) => ppq as f64,
+            // smpte ticks run at a fixed rate
+            TimeDivision::SMPTE { .. } => return self.time * self.division.ticks_per_second().unwrap()
+        };
+
         if self.tempo_events.len() == 0 {
             let bpm = 120.0;
-            return self.time * (self.ppq as f32 * bpm / 60.0);
+            return self.time * (ppq * bpm / 60.0);
         }
 
-        let mut bpm = 60000000.0 / (self.tempo_events[0].tempo as f32);
+        let mut bpm = 60000000.0 / (self.tempo_events[0].tempo as f64);
         let mut last_time = self.tempo_events[0].time_norm;
         let mut last_tick = self.tempo_events[0].time;
 
@@ -512,15 +498,14 @@ impl Renderer {
             if tempo.time_norm > self.time { break; }
             last_time = tempo.time_norm;
             last_tick = tempo.time;
-            bpm = 60000000.0 / (tempo.tempo as f32);
+            bpm = 60000000.0 / (tempo.tempo as f64);
         }
 
-        let tick_pos = ((self.time - last_time) * (self.ppq as f32 * bpm / 60.0)) as u64;
-        return tick_pos as f32 + last_tick as f32;
+        let tick_pos = ((self.time - last_time) * (ppq * bpm / 60.0)) as u64;
+        return tick_pos as f64 + last_tick as f64;
     }
 
     pub unsafe fn draw(&mut self, _context: &ContextWrapper<PossiblyCurrent, Window>) -> () {
-        self.update_meta_info();
         gl::ClearColor(self.background_color[0], self.background_color[1], self.background_color[2], 1.0);
         gl::Clear(gl::COLOR_BUFFER_BIT);
 
@@ -528,7 +513,7 @@ impl Renderer {
         if self.tick_based {
             region_scale *= 960.0;
         }
-        let scale = self.note_size * region_scale;
+        let scale = self.note_size as f64 * region_scale;
         // test
         self.n_program.set_vec2("resolution", self.width, self.height);
 
@@ -599,7 +584,7 @@ impl Renderer {
                         };
 
                         for i in s..notes.len() {
-                            if notes[i].end as f32 / note_fac > time { break; }
+                            if notes[i].end() as f64 / note_fac > time { break; }
                             s += 1;
                         }
                         self.last_note_starts[key] = s;
@@ -609,7 +594,7 @@ impl Renderer {
                     let note_end = {
                         let mut e = note_start;
                         for i in note_start..notes.len() {
-                            if notes[i].start as f32 / note_fac > time + scale { break; }
+                            if notes[i].start as f64 / note_fac > time + scale { break; }
                             e += 1;
                         }
                         e
@@ -619,30 +604,30 @@ impl Renderer {
 
                     if notes.len() > 0 {
                         for n in &notes[note_start..note_end] {
-                            if n.end as f32 / note_fac < time {
+                            if n.end() as f64 / note_fac < time {
                                 self.notes_passed += 1;
                                 continue;
                             }
-                            if n.start as f32 / note_fac < time {
+                            if n.start as f64 / note_fac < time {
                                 pressed = true;
                                 self.polyphony += 1;
-                                color = self.note_color_table[(n.channel as usize * 16 + n.track) % self.note_color_table.len()];
+                                color = self.note_color_table[(n.channel() as usize * 16 + n.track()) % self.note_color_table.len()];
                                 self.render_keys[key].color = color;
-                                if n.velocity > max_vel {
-                                    max_vel = n.velocity;
+                                if n.velocity() > max_vel {
+                                    max_vel = n.velocity();
                                 }
                                 self.render_keys[key].set_key_weight(max_vel);
                             }
                             if key < kbfirstnote || key > kblastnote - 1 { continue; }
 
-                            if n.end as f32 / note_fac < time { continue };
-                            if n.start as f32 / note_fac > time + scale { continue };
+                            if n.end() as f64 / note_fac < time { continue };
+                            if n.start as f64 / note_fac > time + scale { continue };
 
                             self.notes_render[n_id] = RenderNote {
-                                0: [(n.start as f32 / note_fac - time) / scale,
-                                (n.end as f32 / note_fac - time) / scale],
+                                0: [((n.start as f64 / note_fac - time) / scale) as f32,
+                                ((n.end() as f64 / note_fac - time) / scale) as f32],
                                 1: [left, right],
-                                2: self.note_color_table[(n.channel as usize * 16 + n.track) % self.note_color_table.len()]
+                                2: self.note_color_table[(n.channel() as usize * 16 + n.track()) % self.note_color_table.len()]
                             };
                             notes_rendered += 1;
                             n_id += 1;
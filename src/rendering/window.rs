@@ -16,10 +16,18 @@ use rand::seq::SliceRandom;
 use crate::{
     audio::prerender_audio::PrerenderAudio,
     midi::{
+        midi_archive,
+        midi_cache::{self, CachedMIDI},
+        midi_error::MidiLoadError,
         midi_file::MIDIFile, 
-        midi_track_parser::{MIDIEvent, Note, TempoEvent}
+        midi_stream::{MIDIStream, StreamWindow},
+        midi_writer::{ExportStats, MIDIExport, SMFFormat},
+        midi_validator::{self, FindingKind, ValidationReport},
+        load_progress::{LoadProgress, LoadStage},
+        midi_track_parser::{MIDIEvent, MetaEvent, Note, NotePairing, ParseMode, TempoEvent},
+        note_filter::NoteFilter
     }, 
-    rendering::renderer::Renderer, 
+    rendering::{lyrics::Lyrics, renderer::Renderer}, 
     settings::{
         advanced_settings::AdvancedSettings, 
         audio_settings::AudioSettings, 
@@ -32,11 +40,16 @@ use crate::{
         misc::open_directory_in_explorer
     }
 };
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::io;
 use imgui::{Context, Ui};
 
 use rfd::FileDialog;
 
+// how far ahead a streaming midi has to be loaded before playback continues after catching up
+const STREAM_BUFFER_SECS: f64 = 1.0;
+
 pub struct MainWindow {
     pub width: usize,
     pub height: usize,
@@ -49,7 +62,38 @@ pub struct MainWindow {
     popup_ids: u16,
     popup_help_title: &'static str,
     popup_help_text: &'static str,
-    midi_length: f32,
+    popup_error_text: String,
+    load_warnings: Vec<String>,
+    // zip that's waiting for the user to pick which midi to open
+    archive_path: String,
+    archive_entries: Vec<String>,
+    archive_selected: usize,
+    midi_length: f64,
+    // notes the load filters dropped from the loaded midi
+    notes_removed: u64,
+    // bytes used by the midi cache, refreshed whenever the preferences are opened
+    midi_cache_size: u64,
+    // the midi being loaded in the background, if any
+    loading: Option<MidiLoadJob>,
+    // playback caught up with a midi that's still streaming in and is paused until more arrives
+    buffering: bool,
+    // markers and lyrics of the loaded midi, for the overlay
+    lyrics: Lyrics,
+    // every meta event of the loaded midi, nothing else keeps them and exporting needs them
+    meta_evs: Vec<MetaEvent>,
+    // empty for tracks without a name
+    track_names: Vec<String>,
+    // where the loaded midi came from, the path and the zip entry if it's in one
+    midi_source: Option<(String, Option<String>)>,
+    // the validator runs in the background too
+    validation: Option<ValidationJob>,
+    // so does exporting, it works on its own copy of what's loaded
+    export: Option<ExportJob>,
+    // and writing a repaired copy of what was validated, it reads the file again
+    repair: Option<RepairJob>,
+    validation_report: Option<ValidationReport>,
+    // what the report is about, the midi might have been swapped out since
+    validated_source: Option<(String, Option<String>)>,
     prerenderer: PrerenderAudio,
     stream: Option<cpal::Stream>,
 
@@ -101,7 +145,25 @@ impl MainWindow {
             popup_ids: 0,
             popup_help_title: "Help dialog",
             popup_help_text: "Help text",
-            midi_length: 0.0f32,
+            popup_error_text: String::new(),
+            load_warnings: Vec::new(),
+            archive_path: String::new(),
+            archive_entries: Vec::new(),
+            archive_selected: 0,
+            midi_length: 0.0f64,
+            notes_removed: 0,
+            midi_cache_size: 0,
+            loading: None,
+            buffering: false,
+            lyrics: Lyrics::new(),
+            meta_evs: Vec::new(),
+            track_names: Vec::new(),
+            midi_source: None,
+            validation: None,
+            export: None,
+            repair: None,
+            validation_report: None,
+            validated_source: None,
             prerenderer: PrerenderAudio::new(
                 60.0,
                 play_state.clone(),
@@ -287,7 +349,7 @@ impl MainWindow {
                                 Some(VirtualKeyCode::Left) => {
                                     if *state == ElementState::Pressed {
                                         let mut g_time = global_time.lock().unwrap();
-                                        let cur_time = (-3.0f32).max((*g_time).get_time() - 10.0);
+                                        let cur_time = (-3.0f64).max((*g_time).get_time() - 10.0);
                                         (*g_time).navigate(cur_time);
                                         renderer.time_changed = true;
                                         (a_self.lock().unwrap()).prerenderer.play_audio(g_time.get_time(), g_time.speed, false);
@@ -378,7 +440,7 @@ impl MainWindow {
         });
     }
 
-    fn format_time(&mut self, time_secs: f32) -> String {
+    fn format_time(&mut self, time_secs: f64) -> String {
         format!("{}{}:{:05.2}", 
             if time_secs < 0.0 {
                 "-"
@@ -406,22 +468,74 @@ impl MainWindow {
     fn checkbox_with_hint(&mut self, ui: &Ui, label: &'static str, value: &mut bool, help_text: &'static str) -> bool {
         let changed = ui.checkbox(label, value);
         ui.same_line();
-        if ui.button("?") {
-            self.popup_help_title = label;
+        self.hint_button(ui, label, help_text);
+        changed
+    }
+
+    fn hint_button(&mut self, ui: &Ui, title: &'static str, help_text: &'static str) -> () {
+        if ui.button(format!("?##{}", title)) {
+            self.popup_help_title = title;
             self.popup_help_text = help_text;
             self.popup_ids |= 0b10;
         }
-        changed
     }
 
-    fn render_meta_stats_ui(&mut self, renderer: &Renderer, ui: &Ui) -> () {
-        ui.window("meta_info").no_inputs().no_decoration()
-            .position_pivot([1.0,0.0])
-            .size([200.0, 0.0], imgui::Condition::Once)
-            .position([renderer.width - 10.0, 25.0], imgui::Condition::Always)
+    fn show_error(&mut self, text: String) -> () {
+        println!("{}", text);
+        self.popup_error_text = text;
+        self.popup_ids |= 0b100;
+    }
+
+    fn render_lyrics_ui(&mut self, renderer: &Renderer, ui: &Ui) -> () {
+        if !self.visual_settings.show_lyrics || self.lyrics.is_empty() {
+            return;
+        }
+        let time = renderer.time;
+        let marker = self.lyrics.marker_at(time);
+        let (line, next_line) = self.lyrics.lines_at(time);
+        if marker.is_none() && line.is_none() && next_line.is_none() {
+            return;
+        }
+
+        let (y, pivot_y) = match self.visual_settings.lyrics_position {
+            0 => (35.0, 0.0),
+            1 => (renderer.height * 0.5, 0.5),
+            _ => (renderer.height * 0.7, 1.0)
+        };
+        let [r, g, b] = self.visual_settings.lyrics_color;
+        let color = [r, g, b, 1.0];
+        let sung_color = [r, g, b, 0.6];
+        let [r, g, b] = self.visual_settings.lyrics_highlight_color;
+        let highlight_color = [r, g, b, 1.0];
+
+        ui.window("lyrics").no_inputs().no_decoration()
+            .bg_alpha(0.4)
+            .always_auto_resize(true)
+            .position_pivot([0.5, pivot_y])
+            .position([renderer.width * 0.5, y], imgui::Condition::Always)
             .build(|| {
-                if !renderer.curr_marker_text.is_empty() {
-                    ui.text_wrapped(format!("{}", renderer.curr_marker_text));
+                ui.set_window_font_scale(self.visual_settings.lyrics_font_scale);
+                if let Some(marker) = marker {
+                    ui.text_colored(highlight_color, marker);
+                }
+                if let Some(line) = line {
+                    // the syllable being sung is the last one that started
+                    let current = line.syllables.partition_point(|s| s.time <= time);
+                    for (i, syllable) in line.syllables.iter().enumerate() {
+                        if i > 0 {
+                            ui.same_line_with_spacing(0.0, 0.0);
+                        }
+                        let c = if i + 1 == current { highlight_color } else if i < current { sung_color } else { color };
+                        ui.text_colored(c, &syllable.text);
+                    }
+                }
+                if let Some(next_line) = next_line {
+                    for (i, syllable) in next_line.syllables.iter().enumerate() {
+                        if i > 0 {
+                            ui.same_line_with_spacing(0.0, 0.0);
+                        }
+                        ui.text_colored(sung_color, &syllable.text);
+                    }
                 }
             });
     }
@@ -431,8 +545,15 @@ impl MainWindow {
             .position([10.0, 25.0], imgui::Condition::Always)
             .always_auto_resize(true)
             .build(|| {
-                ui.text(format!("Time: {} / {}", self.format_time(renderer.time), self.format_time(self.midi_length)));
+                let loading = match &self.loading {
+                    Some(job) if job.streaming => format!(" (loading, {:.0}%)", job.progress.fraction() * 100.0),
+                    _ => String::new()
+                };
+                ui.text(format!("Time: {} / {}{}", self.format_time(renderer.time), self.format_time(self.midi_length), loading));
                 ui.text(format!("Notes: {} / {}", renderer.notes_passed, renderer.note_count));
+                if self.notes_removed > 0 {
+                    ui.text(format!("Removed by filters: {}", self.notes_removed));
+                }
                 ui.text(format!("Polyphony: {}", renderer.polyphony));
                 ui.text(format!("FPS: {}", self.fps.load(Ordering::Relaxed)));
                 ui.text(format!("Buffer Length: {}", 
@@ -489,6 +610,20 @@ impl MainWindow {
         if ui.color_edit3("Background Color", &mut self.visual_settings.background_color) {
             renderer.background_color = self.visual_settings.background_color;
         }
+
+        ui.new_line();
+        ui.checkbox("Show lyrics and markers", &mut self.visual_settings.show_lyrics);
+        ui.disabled(!self.visual_settings.show_lyrics, || {
+            ui.radio_button("Top", &mut self.visual_settings.lyrics_position, 0);
+            ui.same_line();
+            ui.radio_button("Center", &mut self.visual_settings.lyrics_position, 1);
+            ui.same_line();
+            ui.radio_button("Bottom", &mut self.visual_settings.lyrics_position, 2);
+            ui.set_next_item_width(150.0);
+            ui.slider("Lyrics Size", 0.5, 4.0, &mut self.visual_settings.lyrics_font_scale);
+            ui.color_edit3("Lyrics Color", &mut self.visual_settings.lyrics_color);
+            ui.color_edit3("Highlight Color", &mut self.visual_settings.lyrics_highlight_color);
+        });
         
         let mut palette_names: Vec<_> = self.color_palettes.palette_names.iter().map(String::as_str).collect();
         let mut palette_idx = self.visual_settings.palette_index as i32;
@@ -617,6 +752,24 @@ impl MainWindow {
         if self.input_int_with_hint(ui, "Per Channel Thread Count <!>", &mut per_chan_thread_count, "How many threads XSynth should use for each MIDI channel while rendering audio.\nA value of zero means an automatic thread count.") {
             self.advanced_settings.set_per_chan_thread_count(per_chan_thread_count);
         }
+
+        ui.new_line();
+        let mut use_midi_cache = self.advanced_settings.use_midi_cache;
+        if self.checkbox_with_hint(ui, "Cache Parsed MIDIs", &mut use_midi_cache, "Saves every MIDI to the cache folder after it's been parsed, so opening it again is a lot faster.\nChanging the parsing settings makes a new copy.") {
+            self.advanced_settings.use_midi_cache = use_midi_cache;
+        }
+        ui.disabled(!self.advanced_settings.use_midi_cache, || {
+            let mut limit_mb = self.advanced_settings.midi_cache_limit_mb as i32;
+            if ui.input_int("Cache Size Limit (MB)", &mut limit_mb).build() {
+                self.advanced_settings.set_midi_cache_limit_mb(limit_mb);
+            }
+        });
+        if ui.button(format!("Clear Cache ({:.1} MB used)", self.midi_cache_size as f64 / (1024.0 * 1024.0))) {
+            if let Err(e) = midi_cache::clear() {
+                self.show_error(format!("Couldn't clear the MIDI cache:\n{}", e));
+            }
+            self.midi_cache_size = midi_cache::size();
+        }
     }
 
     fn render_pref_misc_tab(&mut self, renderer: &mut Renderer, ui: &Ui) -> () {
@@ -642,34 +795,85 @@ impl MainWindow {
         ui.new_line();
         ui.text("MIDI Loading");
         ui.checkbox("Tick-based parsing", &mut self.player_settings.tick_based);
+        ui.text("Overlapping notes end");
+        ui.same_line();
+        self.hint_button(ui, "Overlapping notes end", "Which note a note off ends when the same key and channel is already playing more than once.\nNewest first and oldest first pair it with the latest or earliest note, on re-trigger ends the old note as soon as the new one starts.\nSome MIDIs look quite different depending on this. The synth always lets go of the oldest one, but plays as many as are drawn.\nTakes effect on the next MIDI loaded.");
+        ui.radio_button("Newest first", &mut self.player_settings.note_pairing, 0);
+        ui.same_line();
+        ui.radio_button("Oldest first", &mut self.player_settings.note_pairing, 1);
+        ui.same_line();
+        ui.radio_button("On re-trigger", &mut self.player_settings.note_pairing, 2);
+        let mut lenient_parsing = self.player_settings.lenient_parsing;
+        if self.checkbox_with_hint(ui, "Lenient parsing", &mut lenient_parsing, "Recovers from truncated tracks, missing end-of-track events and track lengths that run past the end of the file.\nDisable this to make loading fail on any malformed data instead.") {
+            self.player_settings.lenient_parsing = lenient_parsing;
+        }
+        let mut filter_duplicates = self.player_settings.filter_duplicates;
+        if self.checkbox_with_hint(ui, "Remove duplicate notes", &mut filter_duplicates, "Drops notes that start and end at the same time on the same key and channel as another note.\nTakes effect on the next MIDI loaded.") {
+            self.player_settings.filter_duplicates = filter_duplicates;
+        }
+        let mut min_length = self.player_settings.filter_min_length.min(i32::MAX as u32) as i32;
+        if self.input_int_with_hint(ui, "Min Note Length (ticks)", &mut min_length, "Drops notes shorter than this many ticks. Zero keeps every note.\nTakes effect on the next MIDI loaded.") {
+            self.player_settings.filter_min_length = min_length.max(0) as u32;
+        }
+        let mut min_velocity = self.player_settings.filter_min_velocity as i32;
+        if self.input_int_with_hint(ui, "Min Note Velocity", &mut min_velocity, "Drops notes quieter than this velocity. Zero keeps every note.\nTakes effect on the next MIDI loaded.") {
+            self.player_settings.filter_min_velocity = min_velocity.clamp(0, 127) as u8;
+        }
+        let mut stream_loading = self.player_settings.stream_loading;
+        if self.checkbox_with_hint(ui, "Play while loading", &mut stream_loading, "Starts playing as soon as the beginning of the MIDI is loaded, the rest keeps loading in the background.\nPlayback waits if it catches up. MIDIs loaded this way don't get cached.") {
+            self.player_settings.stream_loading = stream_loading;
+        }
     }
 
     fn render_ui(&mut self, renderer: &mut Renderer, ui: &mut Ui, g_time: &mut GlobalTimer, force_pause: &mut bool) -> () {
+        self.poll_loading(renderer, g_time, force_pause);
+        self.poll_validation();
+        self.poll_export();
+        self.poll_repair();
+
         if self.player_settings.show_ui {
             self.render_stats_ui(renderer, ui);
-            //self.render_meta_stats_ui(renderer, ui);
         }
+        self.render_lyrics_ui(renderer, ui);
 
         ui.main_menu_bar(|| {
             // menu bar
             {
                 ui.menu("File", || {
-                    if ui.menu_item("Load MIDI") {
+                    // a midi that's streaming in is already playing, so it can be replaced like any other
+                    let can_load = self.loading.as_ref().map_or(true, |job| job.streaming);
+                    if ui.menu_item_config("Load MIDI").enabled(can_load).build() {
                         self.load_midi(renderer, g_time, force_pause);
                     }
-                    if ui.menu_item("Unload Current MIDI") {
+                    if ui.menu_item_config("Unload Current MIDI").enabled(can_load).build() {
                         self.unload_midi(renderer, g_time, force_pause);
                     }
+                    // only once it's all there
+                    ui.menu_with_enabled("Export MIDI", self.midi_loaded && self.loading.is_none() && self.export.is_none(), || {
+                        if ui.menu_item("Single Track (Format 0)...") {
+                            self.export_midi(renderer, SMFFormat::SingleTrack);
+                        }
+                        if ui.menu_item("Multi Track (Format 1)...") {
+                            self.export_midi(renderer, SMFFormat::MultiTrack);
+                        }
+                    });
+                    if ui.menu_item_config("Validate MIDI").enabled(self.midi_loaded && self.loading.is_none() && self.validation.is_none() && self.repair.is_none()).build() {
+                        self.validate_midi();
+                    }
                 });
 
                 ui.menu("Edit", || {
                     if ui.menu_item("Preferences...") {
+                        self.midi_cache_size = midi_cache::size();
                         self.popup_ids |= 0b1;
                     }
                 });
 
                 ui.menu("View", || {
                     ui.checkbox("Show UI", &mut self.player_settings.show_ui);
+                    if ui.menu_item_config("Tracks...").enabled(self.midi_loaded).build() {
+                        self.popup_ids |= 0b10000000;
+                    }
                 });
             }
 
@@ -687,7 +891,7 @@ impl MainWindow {
                 renderer.time_changed = true;
                 self.time_nav_changed = true;
             } else {
-                if g_time.paused && !*force_pause {
+                if g_time.paused && !*force_pause && !self.buffering {
                     g_time.play();
                 }
                 if self.time_nav_changed {
@@ -757,52 +961,603 @@ impl MainWindow {
                 }
             });
         }
+
+        // error dialog
+        if self.popup_ids & 0b100 == 0b100 {
+            ui.window("Error")
+                .always_auto_resize(true)
+                .focused(true)
+                .build(|| {
+                ui.text(&self.popup_error_text);
+                ui.new_line();
+                if ui.button("   ok   ") {
+                    self.popup_ids ^= 0b100;
+                }
+            });
+        }
+
+        // warnings from the last midi load
+        if self.popup_ids & 0b1000 == 0b1000 {
+            ui.window("Load Warnings")
+                .size([500.0, 300.0], imgui::Condition::FirstUseEver)
+                .focused(true)
+                .build(|| {
+                ui.text(format!("{} problem(s) were worked around while loading this MIDI:", self.load_warnings.len()));
+                ui.child_window("warning_list").size([0.0, -30.0]).build(|| {
+                    for warning in self.load_warnings.iter() {
+                        ui.text_wrapped(warning);
+                    }
+                });
+                if ui.button("   ok   ") {
+                    self.popup_ids ^= 0b1000;
+                }
+            });
+        }
+
+        // zip with more than one midi in it
+        if self.popup_ids & 0b10000 == 0b10000 {
+            let mut open_entry = false;
+            ui.window("Choose a MIDI")
+                .size([400.0, 300.0], imgui::Condition::FirstUseEver)
+                .focused(true)
+                .build(|| {
+                ui.text(format!("{} contains {} MIDI files:", self.archive_path, self.archive_entries.len()));
+                ui.child_window("archive_entries").size([0.0, -30.0]).build(|| {
+                    for (i, entry) in self.archive_entries.iter().enumerate() {
+                        if ui.selectable_config(entry)
+                            .selected(i == self.archive_selected)
+                            .allow_double_click(true)
+                            .build() {
+                            self.archive_selected = i;
+                            if ui.is_mouse_double_clicked(imgui::MouseButton::Left) {
+                                open_entry = true;
+                            }
+                        }
+                    }
+                });
+                if ui.button("  open  ") {
+                    open_entry = true;
+                }
+                ui.same_line();
+                if ui.button(" cancel ") {
+                    self.popup_ids ^= 0b10000;
+                    self.archive_entries.clear();
+                }
+            });
+
+            if open_entry {
+                self.popup_ids ^= 0b10000;
+                let path = std::mem::take(&mut self.archive_path);
+                let entry = self.archive_entries.swap_remove(self.archive_selected);
+                self.archive_entries.clear();
+                self.open_midi(renderer, g_time, force_pause, path, Some(entry));
+            }
+        }
+
+        // midi loading in the background
+        if self.popup_ids & 0b100000 == 0b100000 {
+            if let Some(job) = &self.loading {
+                ui.window("Loading MIDI")
+                    .always_auto_resize(true)
+                    .focused(true)
+                    .build(|| {
+                    ui.text(&job.path);
+                    imgui::ProgressBar::new(job.progress.fraction())
+                        .size([400.0, 0.0])
+                        .overlay_text(job.progress.describe())
+                        .build(ui);
+                    if job.progress.is_cancelled() {
+                        ui.text("Cancelling...");
+                    } else if ui.button(" cancel ") {
+                        job.progress.cancel();
+                    }
+                });
+            }
+        }
+
+        // validation report of the loaded midi
+        if self.popup_ids & 0b1000000 == 0b1000000 {
+            let mut save_text = false;
+            let mut save_json = false;
+            let mut repair = false;
+            ui.window("MIDI Validation")
+                .size([600.0, 400.0], imgui::Condition::FirstUseEver)
+                .focused(true)
+                .build(|| {
+                if let Some(job) = &self.validation {
+                    ui.text("Validating...");
+                    imgui::ProgressBar::new(job.progress.fraction())
+                        .size([400.0, 0.0])
+                        .overlay_text(job.progress.describe())
+                        .build(ui);
+                    if ui.button(" cancel ") {
+                        job.progress.cancel();
+                    }
+                    return;
+                }
+                let Some(report) = &self.validation_report else {
+                    return;
+                };
+
+                ui.text_wrapped(&report.path);
+                ui.text(format!("{} problem(s) found in {} tracks", report.total(), report.track_count));
+                for warning in report.file_warnings.iter() {
+                    ui.text_wrapped(warning);
+                }
+                for kind in FindingKind::ALL {
+                    let count = report.counts[kind as usize];
+                    if count > 0 {
+                        ui.bullet_text(format!("{}: {}", kind.name(), count));
+                    }
+                }
+                if report.findings.len() as u64 != report.total() {
+                    ui.text_disabled(format!("Only the first {} of each kind per track are listed.", midi_validator::MAX_KEPT_PER_KIND));
+                }
+
+                // the progress of a repaired copy goes under the buttons
+                let bottom = if self.repair.is_some() { -60.0 } else { -30.0 };
+                ui.child_window("finding_list").size([0.0, bottom]).border(true).build(|| {
+                    // black midis can have a lot of these
+                    let clipper = imgui::ListClipper::new(report.findings.len() as i32).begin(ui);
+                    for i in clipper.iter() {
+                        let f = &report.findings[i as usize];
+                        ui.text(format!("track {}, tick {}: {}: {}", f.track, f.tick, f.kind.name(), f.detail));
+                    }
+                });
+                ui.disabled(self.repair.is_some(), || {
+                    save_text = ui.button("Save as Text...");
+                    ui.same_line();
+                    save_json = ui.button("Save as JSON...");
+                    ui.same_line();
+                    repair = ui.button("Write Repaired Copy...");
+                    ui.same_line();
+                    if ui.button(" close ") {
+                        self.popup_ids ^= 0b1000000;
+                    }
+                });
+                if let Some(job) = &self.repair {
+                    imgui::ProgressBar::new(job.progress.fraction())
+                        .size([400.0, 0.0])
+                        .overlay_text(job.progress.describe())
+                        .build(ui);
+                    ui.same_line();
+                    if job.progress.is_cancelled() {
+                        ui.text("Cancelling...");
+                    } else if ui.button(" cancel ") {
+                        job.progress.cancel();
+                    }
+                }
+            });
+
+            if save_text || save_json {
+                self.save_validation_report(save_json);
+            }
+            if repair {
+                self.write_repaired_copy();
+            }
+        }
+
+        // the loaded midi's tracks and their names
+        if self.popup_ids & 0b10000000 == 0b10000000 {
+            ui.window("Tracks")
+                .size([400.0, 300.0], imgui::Condition::FirstUseEver)
+                .build(|| {
+                ui.text(format!("{} tracks", self.track_names.len()));
+                ui.child_window("track_list").size([0.0, -30.0]).border(true).build(|| {
+                    let clipper = imgui::ListClipper::new(self.track_names.len() as i32).begin(ui);
+                    for i in clipper.iter() {
+                        match self.track_names[i as usize].as_str() {
+                            "" => ui.text_disabled(format!("Track {}: (no name)", i)),
+                            name => ui.text(format!("Track {}: {}", i, name))
+                        }
+                    }
+                });
+                if ui.button("   ok   ") {
+                    self.popup_ids ^= 0b10000000;
+                }
+            });
+        }
+    }
+
+    fn validate_midi(&mut self) -> () {
+        let Some((path, zip_entry)) = self.midi_source.clone() else {
+            return;
+        };
+        self.validated_source = Some((path.clone(), zip_entry.clone()));
+        self.validation_report = None;
+
+        let progress = Arc::new(LoadProgress::new());
+        let (tx, rx) = mpsc::channel();
+        {
+            let progress = Arc::clone(&progress);
+            thread::spawn(move || {
+                let _ = tx.send(midi_validator::validate(&path, zip_entry.as_deref(), progress));
+            });
+        }
+        self.validation = Some(ValidationJob { progress, result: rx });
+        self.popup_ids |= 0b1000000;
+    }
+
+    fn poll_validation(&mut self) -> () {
+        let Some(job) = &self.validation else {
+            return;
+        };
+        let result = match job.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => None
+        };
+        self.validation = None;
+
+        match result {
+            Some(Ok(report)) => self.validation_report = Some(report),
+            Some(Err(MidiLoadError::Cancelled)) => self.popup_ids &= !0b1000000,
+            Some(Err(e)) => {
+                self.popup_ids &= !0b1000000;
+                self.show_error(format!("Couldn't validate the MIDI:\n{}", e));
+            }
+            None => {
+                self.popup_ids &= !0b1000000;
+                self.show_error(String::from("Couldn't validate the MIDI:\nthe validation thread crashed"));
+            }
+        }
+    }
+
+    fn save_validation_report(&mut self, json: bool) -> () {
+        let Some(report) = &self.validation_report else {
+            return;
+        };
+        let (filter, ext) = if json { ("JSON", "json") } else { ("Text", "txt") };
+        let file_diag = FileDialog::new()
+            .add_filter(filter, &[ext])
+            .set_file_name(format!("validation.{}", ext))
+            .set_title("Save Validation Report");
+        let Some(path) = file_diag.save_file() else {
+            return;
+        };
+
+        let contents = if json { report.to_json() } else { report.to_text() };
+        if let Err(e) = std::fs::write(&path, contents) {
+            self.show_error(format!("Couldn't save {}:\n{}", path.display(), e));
+        }
+    }
+
+    // parses the midi again and writes it back out in the background
+    fn write_repaired_copy(&mut self) {
+        let Some((path, zip_entry)) = self.validated_source.clone() else {
+            return;
+        };
+        let file_diag = FileDialog::new()
+            .add_filter("MIDI File", &["mid"])
+            .set_file_name("repaired.mid")
+            .set_title("Write Repaired Copy");
+        let Some(out) = file_diag.save_file() else {
+            return;
+        };
+        let out = String::from(out.to_str().unwrap());
+
+        let progress = Arc::new(LoadProgress::new());
+        let (tx, rx) = mpsc::channel();
+        {
+            let progress = Arc::clone(&progress);
+            let out = out.clone();
+            thread::spawn(move || {
+                let _ = tx.send(midi_validator::write_repaired(&path, zip_entry.as_deref(), &out, progress));
+            });
+        }
+        self.repair = Some(RepairJob { path: out, progress, result: rx });
+    }
+
+    fn poll_repair(&mut self) {
+        let Some(job) = &self.repair else {
+            return;
+        };
+        let result = match job.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => None
+        };
+        let path = self.repair.take().unwrap().path;
+
+        match result {
+            Some(Ok(stats)) => println!("wrote a repaired copy with {} notes to {}", stats.notes_written, path),
+            Some(Err(MidiLoadError::Cancelled)) => {}
+            Some(Err(e)) => self.show_error(format!("Couldn't write {}:\n{}", path, e)),
+            None => self.show_error(format!("Couldn't write {}:\nthe repair thread crashed", path))
+        }
     }
 
     fn load_midi(&mut self, renderer: &mut Renderer, g_time: &mut GlobalTimer, force_pause: &mut bool) {
         let file_diag = FileDialog::new()
-            .add_filter("MIDI File", &["mid","midi"])
+            .add_filter("MIDI File", &["mid","midi","rmi","kar","gz","xz","zip"])
             .set_title("Open a MIDI File");
         if let Some(path) = file_diag.pick_file() {
-            if self.midi_loaded {
+            let path = String::from(path.to_str().unwrap());
+
+            // let the user pick if there's more than one midi in a zip
+            let entries = match midi_archive::zip_midi_entries(&path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    self.show_error(format!("Couldn't open {}:\n{}", path, e));
+                    return;
+                }
+            };
+            if entries.len() > 1 {
+                self.archive_path = path;
+                self.archive_entries = entries;
+                self.archive_selected = 0;
+                self.popup_ids |= 0b10000;
+                return;
+            }
+
+            self.open_midi(renderer, g_time, force_pause, path, entries.into_iter().next());
+        }
+    }
+
+    // writes out what's loaded, with transpose and the load filters applied. the notes and events
+    // get copied, so the midi can be swapped out while the export is still running
+    fn export_midi(&mut self, renderer: &Renderer, format: SMFFormat) -> () {
+        let file_diag = FileDialog::new()
+            .add_filter("MIDI File", &["mid"])
+            .set_file_name("export.mid")
+            .set_title("Export MIDI");
+        let Some(path) = file_diag.save_file() else {
+            return;
+        };
+        let path = String::from(path.to_str().unwrap());
+
+        let division = renderer.division;
+        let tick_based = renderer.tick_based;
+        let transpose = renderer.notes_transpose;
+        let tempo_evs = renderer.tempo_events.clone();
+        let midi_evs = self.prerenderer.midi_events().clone();
+        let meta_evs = self.meta_evs.clone();
+        let notes = renderer.render_notes.clone();
+
+        let (tx, rx) = mpsc::channel();
+        {
+            let path = path.clone();
+            thread::spawn(move || {
+                let export = MIDIExport {
+                    division,
+                    tick_based,
+                    transpose,
+                    tempo_evs: &tempo_evs,
+                    midi_evs: &midi_evs,
+                    meta_evs: &meta_evs,
+                    notes: &notes
+                };
+                let _ = tx.send(export.save(&path, format));
+            });
+        }
+        self.export = Some(ExportJob { path, result: rx });
+    }
+
+    fn poll_export(&mut self) -> () {
+        let Some(job) = &self.export else {
+            return;
+        };
+        let result = match job.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => None
+        };
+        let path = self.export.take().unwrap().path;
+
+        match result {
+            Some(Ok(stats)) => {
+                println!("exported {} notes in {} tracks to {}", stats.notes_written, stats.track_count, path);
+                if stats.notes_skipped > 0 {
+                    self.show_error(format!("{} notes were left out of {}, they're outside of the 0-127 key range.", stats.notes_skipped, path));
+                }
+            }
+            Some(Err(e)) => self.show_error(format!("Couldn't export {}:\n{}", path, e)),
+            None => self.show_error(format!("Couldn't export {}:\nthe export thread crashed", path))
+        }
+    }
+
+    fn open_midi(&mut self, renderer: &mut Renderer, g_time: &mut GlobalTimer, force_pause: &mut bool, path: String, zip_entry: Option<String>) {
+        if self.midi_loaded {
+            self.unload_midi(renderer, g_time, force_pause);
+        }
+
+        let parse_mode = if self.player_settings.lenient_parsing {
+            ParseMode::Lenient
+        } else {
+            ParseMode::Strict
+        };
+        let note_pairing = match self.player_settings.note_pairing {
+            1 => NotePairing::Fifo,
+            2 => NotePairing::TruncatePrevious,
+            _ => NotePairing::Lifo
+        };
+        let note_filter = NoteFilter {
+            remove_duplicates: self.player_settings.filter_duplicates,
+            min_length: self.player_settings.filter_min_length,
+            min_velocity: self.player_settings.filter_min_velocity
+        };
+
+        let tick_based = self.player_settings.tick_based;
+        let stream = self.player_settings.stream_loading;
+        let cache_limit = if self.advanced_settings.use_midi_cache {
+            Some(self.advanced_settings.midi_cache_limit_mb as u64 * 1024 * 1024)
+        } else {
+            None
+        };
+
+        self.midi_source = Some((path.clone(), zip_entry.clone()));
+
+        // parsing takes a while for big midis, so it happens on its own thread and
+        // poll_loading picks up the result
+        let progress = Arc::new(LoadProgress::new());
+        let (tx, rx) = mpsc::channel();
+        {
+            let path = path.clone();
+            let progress = Arc::clone(&progress);
+            thread::spawn(move || {
+                if stream {
+                    stream_midi_data(&path, zip_entry.as_deref(), tick_based, parse_mode, note_pairing, note_filter, cache_limit.is_some(), progress, &tx);
+                } else {
+                    let _ = tx.send(load_midi_data(&path, zip_entry.as_deref(), tick_based, parse_mode, note_pairing, note_filter, cache_limit, progress).map(LoadMessage::Loaded));
+                }
+            });
+        }
+
+        self.loading = Some(MidiLoadJob {
+            path,
+            tick_based,
+            streaming: false,
+            progress,
+            result: rx
+        });
+        self.popup_ids |= 0b100000;
+    }
+
+    fn poll_loading(&mut self, renderer: &mut Renderer, g_time: &mut GlobalTimer, force_pause: &mut bool) -> () {
+        while let Some(job) = &mut self.loading {
+            let result = match job.result.try_recv() {
+                Ok(result) => Some(result),
+                Err(mpsc::TryRecvError::Empty) => break,
+                // the thread died without sending anything
+                Err(mpsc::TryRecvError::Disconnected) => None
+            };
+
+            // streamed midis keep sending windows until the last one
+            let result = match result {
+                Some(Ok(LoadMessage::Window(window))) => {
+                    let first = !job.streaming;
+                    let tick_based = job.tick_based;
+                    job.streaming = true;
+                    if window.finished {
+                        self.loading = None;
+                    }
+                    self.add_midi_window(renderer, g_time, force_pause, window, tick_based, first);
+                    continue;
+                },
+                result => result
+            };
+
+            let job = self.loading.take().unwrap();
+            self.popup_ids &= !0b100000;
+            if job.streaming {
+                // the part that did load isn't worth keeping
                 self.unload_midi(renderer, g_time, force_pause);
             }
+            match result {
+                Some(Ok(LoadMessage::Loaded(mid))) => self.finish_loading(renderer, g_time, force_pause, mid, job.tick_based),
+                Some(Ok(LoadMessage::Window(_))) => unreachable!(),
+                Some(Err(MidiLoadError::Cancelled)) => println!("stopped loading {}", job.path),
+                Some(Err(e)) => self.show_error(format!("Couldn't load {}:\n{}", job.path, e)),
+                None => self.show_error(format!("Couldn't load {}:\nthe loading thread crashed", job.path))
+            }
+        }
 
-            let mid: MIDIFile = MIDIFile::new(String::from(path.to_str().unwrap()), self.player_settings.tick_based).unwrap();
-            renderer.ppq = mid.ppq;
-            self.midi_key_range = mid.key_range;
+        // playback caught up with the loading, wait until there's a bit more to play
+        let streaming = self.loading.as_ref().map_or(false, |job| job.streaming);
+        let time = g_time.get_time();
+        if streaming && !g_time.paused && time >= self.midi_length {
+            g_time.pause();
+            self.buffering = true;
+        } else if self.buffering && (!streaming || time + STREAM_BUFFER_SECS <= self.midi_length) {
+            self.buffering = false;
+        }
+    }
 
-            renderer.first_key = mid.key_range[0] as usize;
-            renderer.last_key = mid.key_range[1] as usize;
-            renderer.note_count = mid.note_counts.iter().sum::<u64>() as usize;
-            
-            let mut evs: Vec<MIDIEvent> = Vec::new();
-            let mut notes: Vec<Vec<Note>> = Vec::new();
-            let mut tempos: Vec<TempoEvent> = Vec::new();
-            mid.get_sequences(&mut evs, &mut notes, &mut tempos);
-
-            renderer.tick_based = self.player_settings.tick_based;
-            renderer.tempo_events = tempos;
-            renderer.set_notes(notes);
+    fn finish_loading(&mut self, renderer: &mut Renderer, g_time: &mut GlobalTimer, force_pause: &mut bool, mid: CachedMIDI, tick_based: bool) -> () {
+        let window = StreamWindow {
+            until: mid.midi_evs.last().map_or(0.0, |ev| ev.time),
+            midi_evs: mid.midi_evs,
+            notes: mid.notes,
+            tempo_evs: mid.tempo_evs,
+            meta_evs: mid.meta_evs,
+            division: mid.division,
+            key_range: mid.key_range,
+            port_count: mid.port_count,
+            note_count: mid.note_counts.iter().sum(),
+            notes_removed: mid.notes_removed,
+            track_names: mid.track_names,
+            warnings: mid.warnings,
+            finished: true
+        };
+        self.add_midi_window(renderer, g_time, force_pause, window, tick_based, true);
+    }
+
+    // a whole midi is a single window. streamed ones start playing on their first window,
+    // the later ones get added on to the end
+    fn add_midi_window(&mut self, renderer: &mut Renderer, g_time: &mut GlobalTimer, force_pause: &mut bool, window: StreamWindow, tick_based: bool, first: bool) -> () {
+        self.midi_key_range = window.key_range;
+        renderer.first_key = window.key_range[0] as usize;
+        renderer.last_key = window.key_range[1] as usize;
+        // note_count is everything parsed, the filtered notes never make it to the renderer
+        renderer.note_count = window.note_count.saturating_sub(window.notes_removed) as usize;
+        self.notes_removed = window.notes_removed;
+        self.track_names = window.track_names;
+        self.midi_length = window.until;
+
+        if first {
+            self.load_warnings.clear();
+        }
+        self.load_warnings.extend(window.warnings);
+        if window.finished && !self.load_warnings.is_empty() {
+            self.popup_ids |= 0b1000;
+        }
+
+        if first {
+            self.popup_ids &= !0b100000;
+            renderer.division = window.division;
+            renderer.tick_based = tick_based;
+            renderer.tempo_events = window.tempo_evs;
+            self.lyrics.clear();
+            self.lyrics.add_events(&window.meta_evs);
+            self.meta_evs = window.meta_evs;
+            renderer.set_notes(window.notes);
             renderer.time = -3.0;
             g_time.play();
             *force_pause = false;
-            self.midi_length = evs.last().unwrap().time;
 
-            self.prerenderer.set_midi_events(evs);
+            // the synth gets remade for the new port count, the main loop starts it back up
+            self.prerenderer.stop();
+            self.prerenderer.set_port_count(window.port_count);
+            self.stream_playing = false;
+            self.prerenderer.set_midi_events(window.midi_evs, window.finished);
             self.midi_loaded = true;
+        } else {
+            renderer.tempo_events.extend(window.tempo_evs);
+            self.lyrics.add_events(&window.meta_evs);
+            self.meta_evs.extend(window.meta_evs);
+            renderer.append_notes(window.notes);
+            // a port that first shows up in this window would stay muted, so the synth gets remade
+            // for it. the main loop starts it back up at the current time, same as after seeking
+            if window.port_count > self.prerenderer.port_count() {
+                self.prerenderer.stop();
+                self.prerenderer.set_port_count(window.port_count);
+                self.stream_playing = false;
+            }
+            self.prerenderer.append_midi_events(window.midi_evs, window.finished);
         }
     }
 
     fn unload_midi(&mut self, renderer: &mut Renderer, g_time: &mut GlobalTimer, force_pause: &mut bool) {
+        // stops a midi that's still streaming in
+        if let Some(job) = self.loading.take() {
+            job.progress.cancel();
+            self.popup_ids &= !0b100000;
+        }
+        self.buffering = false;
+
         if self.midi_loaded == true {
             self.midi_key_range = [0, 128];
+            self.notes_removed = 0;
             renderer.first_key = 0;
             renderer.last_key = 127;
 
             renderer.set_notes(Vec::new());
             renderer.tempo_events.clear();
-            renderer.meta_events.clear();
+            self.lyrics.clear();
+            self.meta_evs.clear();
+            self.track_names.clear();
+            self.popup_ids &= !0b10000000;
+            self.midi_source = None;
 
             renderer.time = 0.0;
             //self.stream.pause().unwrap();
@@ -827,4 +1582,119 @@ impl MainWindow {
         self.player_settings.save_settings();
         self.advanced_settings.save_settings();
     }
+}
+
+struct ValidationJob {
+    progress: Arc<LoadProgress>,
+    result: mpsc::Receiver<Result<ValidationReport, MidiLoadError>>
+}
+
+struct ExportJob {
+    path: String,
+    result: mpsc::Receiver<io::Result<ExportStats>>
+}
+
+struct RepairJob {
+    // where the repaired copy goes
+    path: String,
+    progress: Arc<LoadProgress>,
+    result: mpsc::Receiver<Result<ExportStats, MidiLoadError>>
+}
+
+struct MidiLoadJob {
+    path: String,
+    // what the notes' times are in, the setting might change while loading
+    tick_based: bool,
+    // set once the first window of a streamed midi arrived and it started playing
+    streaming: bool,
+    progress: Arc<LoadProgress>,
+    result: mpsc::Receiver<Result<LoadMessage, MidiLoadError>>
+}
+
+enum LoadMessage {
+    Loaded(CachedMIDI),
+    // the next piece of a midi that's streaming in, see MIDIStream
+    Window(StreamWindow)
+}
+
+// runs on the loading thread. cache_limit is None when the cache is turned off
+fn load_midi_data(path: &str, zip_entry: Option<&str>, tick_based: bool, parse_mode: ParseMode, note_pairing: NotePairing, note_filter: NoteFilter, cache_limit: Option<u64>, progress: Arc<LoadProgress>) -> Result<CachedMIDI, MidiLoadError> {
+    let cache_key = match cache_limit {
+        Some(_) => midi_cache::cache_key(path, zip_entry, tick_based, parse_mode, note_pairing, note_filter).ok(),
+        None => None
+    };
+    if let Some(cached) = cache_key.and_then(midi_cache::load) {
+        println!("loaded {} from the cache", path);
+        return Ok(cached);
+    }
+
+    let mid = parse_midi(path, zip_entry, tick_based, parse_mode, note_pairing, note_filter, Arc::clone(&progress))?;
+    if let (Some(key), Some(limit)) = (cache_key, cache_limit) {
+        progress.set_stage(LoadStage::Caching);
+        if let Err(e) = midi_cache::store(key, &mid, limit) {
+            println!("couldn't write the midi cache: {}", e);
+        }
+    }
+    Ok(mid)
+}
+
+// runs on the loading thread too. hands the midi over a window at a time as it gets parsed.
+// the windows are gone to the ui by the end, so streamed midis don't get written to the cache
+fn stream_midi_data(path: &str, zip_entry: Option<&str>, tick_based: bool, parse_mode: ParseMode, note_pairing: NotePairing, note_filter: NoteFilter, use_cache: bool, progress: Arc<LoadProgress>, tx: &mpsc::Sender<Result<LoadMessage, MidiLoadError>>) -> () {
+    if use_cache {
+        if let Some(cached) = midi_cache::cache_key(path, zip_entry, tick_based, parse_mode, note_pairing, note_filter).ok().and_then(midi_cache::load) {
+            println!("loaded {} from the cache", path);
+            let _ = tx.send(Ok(LoadMessage::Loaded(cached)));
+            return;
+        }
+    }
+
+    let mut stream = match MIDIStream::open(path, zip_entry, tick_based, parse_mode, note_pairing, note_filter, progress) {
+        Ok(stream) => stream,
+        Err(e) => {
+            let _ = tx.send(Err(e));
+            return;
+        }
+    };
+
+    loop {
+        let msg = match stream.next_window() {
+            Ok(Some(window)) => Ok(LoadMessage::Window(window)),
+            Ok(None) => return,
+            Err(e) => Err(e)
+        };
+        let failed = msg.is_err();
+        // a dropped receiver means the ui doesn't want the rest anymore
+        if tx.send(msg).is_err() || failed {
+            return;
+        }
+    }
+}
+
+// the slow path of load_midi_data, for midis that aren't cached
+fn parse_midi(path: &str, zip_entry: Option<&str>, tick_based: bool, parse_mode: ParseMode, note_pairing: NotePairing, note_filter: NoteFilter, progress: Arc<LoadProgress>) -> Result<CachedMIDI, MidiLoadError> {
+    let mut mid: MIDIFile = match zip_entry {
+        Some(entry) => MIDIFile::from_zip_entry(path.to_string(), entry, tick_based, parse_mode, note_pairing, progress)?,
+        None => MIDIFile::new(path.to_string(), tick_based, parse_mode, note_pairing, progress)?
+    };
+
+    let mut midi_evs: Vec<MIDIEvent> = Vec::new();
+    let mut notes: Vec<Vec<Note>> = Vec::new();
+    let mut tempo_evs: Vec<TempoEvent> = Vec::new();
+    let mut meta_evs: Vec<MetaEvent> = Vec::new();
+    mid.get_sequences(&mut midi_evs, &mut notes, &mut tempo_evs, &mut meta_evs, note_filter)?;
+
+    Ok(CachedMIDI {
+        division: mid.division,
+        key_range: mid.key_range,
+        port_count: mid.port_count,
+        note_counts: std::mem::take(&mut mid.note_counts),
+        track_names: std::mem::take(&mut mid.track_names),
+        notes_removed: mid.notes_removed,
+        warnings: std::mem::take(&mut mid.warnings),
+        tempo_evs,
+        midi_evs,
+        meta_evs,
+        notes
+    })
 }
\ No newline at end of file
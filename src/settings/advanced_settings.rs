@@ -7,7 +7,9 @@ pub struct AdvancedSettings {
     pub limit_fps: bool,
     pub max_fps: usize,
     pub per_key_thread_count: usize,
-    pub per_chan_thread_count: usize
+    pub per_chan_thread_count: usize,
+    pub use_midi_cache: bool,
+    pub midi_cache_limit_mb: usize
 }
 
 impl AdvancedSettings {
@@ -17,6 +19,8 @@ impl AdvancedSettings {
             max_fps: 60,
             per_key_thread_count: 1,
             per_chan_thread_count: 0,
+            use_midi_cache: true,
+            midi_cache_limit_mb: 4096
         }
     }
 
@@ -49,6 +53,13 @@ impl AdvancedSettings {
         self.per_chan_thread_count = per_chan_threads as usize;
     }
     
+    pub fn set_midi_cache_limit_mb(&mut self, mut limit_mb: i32) -> () {
+        if limit_mb < 0 {
+            limit_mb = 0;
+        }
+        self.midi_cache_limit_mb = limit_mb as usize;
+    }
+
     pub fn load_settings(&mut self) {
         let mut config = get_config();
         if !config.sections().contains(&String::from("advanced")) {
@@ -56,6 +67,8 @@ impl AdvancedSettings {
             config.set("advanced", "max_fps", Some(self.max_fps.to_string()));
             config.set("advanced", "per_key_thread_count", Some(self.per_key_thread_count.to_string()));
             config.set("advanced", "per_chan_thread_count", Some(self.per_chan_thread_count.to_string()));
+            config.set("advanced", "use_midi_cache", Some(self.use_midi_cache.to_string()));
+            config.set("advanced", "midi_cache_limit_mb", Some(self.midi_cache_limit_mb.to_string()));
         } else {
             //self.show_ui = config.getbool("player", "show_ui").unwrap().unwrap();
             self.limit_fps = config.getbool("advanced", "limit_fps").unwrap()
@@ -66,6 +79,10 @@ impl AdvancedSettings {
                 .unwrap_or(1) as usize;
             self.per_chan_thread_count = config.getuint("advanced", "per_chan_thread_count").unwrap()
                 .unwrap_or(0) as usize;
+            self.use_midi_cache = config.getbool("advanced", "use_midi_cache").unwrap()
+                .unwrap_or(true);
+            self.midi_cache_limit_mb = config.getuint("advanced", "midi_cache_limit_mb").unwrap()
+                .unwrap_or(4096) as usize;
         }
     }
 
@@ -76,6 +93,8 @@ impl AdvancedSettings {
         config.set("advanced", "max_fps", Some(self.max_fps.to_string()));
         config.set("advanced", "per_key_thread_count", Some(self.per_key_thread_count.to_string()));
         config.set("advanced", "per_chan_thread_count", Some(self.per_chan_thread_count.to_string()));
+        config.set("advanced", "use_midi_cache", Some(self.use_midi_cache.to_string()));
+        config.set("advanced", "midi_cache_limit_mb", Some(self.midi_cache_limit_mb.to_string()));
         config.write(absolute("./config.ini").unwrap()).unwrap();
     }
 }
\ No newline at end of file
@@ -4,6 +4,12 @@ use super::config::get_config;
 pub struct PlayerSettings {
     pub show_ui: bool,
     pub tick_based: bool,
+    pub lenient_parsing: bool,
+    pub stream_loading: bool,
+    pub note_pairing: usize,
+    pub filter_duplicates: bool,
+    pub filter_min_length: u32,
+    pub filter_min_velocity: u8,
     pub fullscreen: bool
 }
 
@@ -12,6 +18,12 @@ impl PlayerSettings {
         Self {
             show_ui: true,
             tick_based: true,
+            lenient_parsing: true,
+            stream_loading: false,
+            note_pairing: 0, /* 0: LIFO, 1: FIFO, 2: truncate previous */
+            filter_duplicates: false,
+            filter_min_length: 0,
+            filter_min_velocity: 0,
             fullscreen: false
         }
     }
@@ -21,9 +33,21 @@ impl PlayerSettings {
         if !config.sections().contains(&String::from("player")) {
             config.set("player", "show_ui", Some(true.to_string()));
             config.set("player", "tick_based", Some(true.to_string()));
+            config.set("player", "lenient_parsing", Some(true.to_string()));
+            config.set("player", "stream_loading", Some(false.to_string()));
+            config.set("player", "note_pairing", Some(0.to_string()));
+            config.set("player", "filter_duplicates", Some(false.to_string()));
+            config.set("player", "filter_min_length", Some(0.to_string()));
+            config.set("player", "filter_min_velocity", Some(0.to_string()));
         } else {
             self.show_ui = config.getbool("player", "show_ui").unwrap().unwrap_or(true);
             self.tick_based = config.getbool("player", "tick_based").unwrap().unwrap_or(true);
+            self.lenient_parsing = config.getbool("player", "lenient_parsing").unwrap().unwrap_or(true);
+            self.stream_loading = config.getbool("player", "stream_loading").unwrap().unwrap_or(false);
+            self.note_pairing = config.getuint("player", "note_pairing").unwrap().unwrap_or(0) as usize;
+            self.filter_duplicates = config.getbool("player", "filter_duplicates").unwrap().unwrap_or(false);
+            self.filter_min_length = config.getuint("player", "filter_min_length").unwrap().unwrap_or(0).min(u32::MAX as u64) as u32;
+            self.filter_min_velocity = config.getuint("player", "filter_min_velocity").unwrap().unwrap_or(0).min(127) as u8;
         }
     }
 
@@ -31,6 +55,12 @@ impl PlayerSettings {
         let mut config = get_config();
         config.set("player", "show_ui", Some(self.show_ui.to_string()));
         config.set("player", "tick_based", Some(self.tick_based.to_string()));
+        config.set("player", "lenient_parsing", Some(self.lenient_parsing.to_string()));
+        config.set("player", "stream_loading", Some(self.stream_loading.to_string()));
+        config.set("player", "note_pairing", Some(self.note_pairing.to_string()));
+        config.set("player", "filter_duplicates", Some(self.filter_duplicates.to_string()));
+        config.set("player", "filter_min_length", Some(self.filter_min_length.to_string()));
+        config.set("player", "filter_min_velocity", Some(self.filter_min_velocity.to_string()));
         config.write(absolute("./config.ini").unwrap()).unwrap();
     }
 }
\ No newline at end of file
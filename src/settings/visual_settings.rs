@@ -8,7 +8,12 @@ pub struct VisualSettings {
     pub palette_index: usize,
     pub keyboard_range_id: usize,
     pub kb_first_key: usize,
-    pub kb_last_key: usize
+    pub kb_last_key: usize,
+    pub show_lyrics: bool,
+    pub lyrics_position: usize,
+    pub lyrics_font_scale: f32,
+    pub lyrics_color: [f32; 3],
+    pub lyrics_highlight_color: [f32; 3]
 }
 
 impl VisualSettings {
@@ -20,7 +25,12 @@ impl VisualSettings {
             palette_index: 0,
             keyboard_range_id: 0, /* 0: 88 keys, 1: 128 keys, 2: 256 keys, 3: MIDI key range, 4: Custom */
             kb_first_key: 21,
-            kb_last_key: 108
+            kb_last_key: 108,
+            show_lyrics: true,
+            lyrics_position: 2, /* 0: Top, 1: Center, 2: Bottom */
+            lyrics_font_scale: 1.5,
+            lyrics_color: [1.0, 1.0, 1.0],
+            lyrics_highlight_color: [1.0, 0.8, 0.0]
         }
     }
 
@@ -64,6 +74,11 @@ impl VisualSettings {
             config.set("visual", "keyboard_range_id", Some(self.keyboard_range_id.to_string()));
             config.set("visual", "kb_first_key", Some(self.kb_first_key.to_string()));
             config.set("visual", "kb_last_key", Some(self.kb_last_key.to_string()));
+            config.set("visual", "show_lyrics", Some(self.show_lyrics.to_string()));
+            config.set("visual", "lyrics_position", Some(self.lyrics_position.to_string()));
+            config.set("visual", "lyrics_font_scale", Some(self.lyrics_font_scale.to_string()));
+            config.set("visual", "lyrics_color", Some(encode_rgb(self.lyrics_color).to_string()));
+            config.set("visual", "lyrics_highlight_color", Some(encode_rgb(self.lyrics_highlight_color).to_string()));
 
             println!("No visual settings found, default values loaded.");
         } else {
@@ -81,6 +96,16 @@ impl VisualSettings {
                 .unwrap_or(21) as usize;
             self.kb_last_key = config.getuint("visual", "kb_last_key").unwrap()
                 .unwrap_or(108) as usize;
+            self.show_lyrics = config.getbool("visual", "show_lyrics").unwrap()
+                .unwrap_or(true);
+            self.lyrics_position = config.getuint("visual", "lyrics_position").unwrap()
+                .unwrap_or(2) as usize;
+            self.lyrics_font_scale = config.getfloat("visual", "lyrics_font_scale").unwrap()
+                .unwrap_or(1.5) as f32;
+            self.lyrics_color = decode_rgb(config.getuint("visual", "lyrics_color").unwrap()
+                .unwrap_or(0xFFFFFF) as u32);
+            self.lyrics_highlight_color = decode_rgb(config.getuint("visual", "lyrics_highlight_color").unwrap()
+                .unwrap_or(0xFFCC00) as u32);
         }
     }
 
@@ -124,6 +149,11 @@ impl VisualSettings {
         config.set("visual", "keyboard_range_id", Some(self.keyboard_range_id.to_string()));
         config.set("visual", "kb_first_key", Some(self.kb_first_key.to_string()));
         config.set("visual", "kb_last_key", Some(self.kb_last_key.to_string()));
+        config.set("visual", "show_lyrics", Some(self.show_lyrics.to_string()));
+        config.set("visual", "lyrics_position", Some(self.lyrics_position.to_string()));
+        config.set("visual", "lyrics_font_scale", Some(self.lyrics_font_scale.to_string()));
+        config.set("visual", "lyrics_color", Some(encode_rgb(self.lyrics_color).to_string()));
+        config.set("visual", "lyrics_highlight_color", Some(encode_rgb(self.lyrics_highlight_color).to_string()));
         config.write(std::path::absolute("./config.ini").unwrap()).unwrap();
     }
 }
\ No newline at end of file
@@ -2,7 +2,7 @@ use std::time::Instant;
 
 pub struct GlobalTimer {
     pub time: Instant,
-    pub midi_time: f32,
+    pub midi_time: f64,
     pub paused: bool,
     pub speed: f32,
 
@@ -27,7 +27,7 @@ impl GlobalTimer {
 
     pub fn pause(&mut self) -> () {
         if self.paused { return; }
-        self.midi_time += self.time.elapsed().as_secs_f32() * self.speed;
+        self.midi_time += self.time.elapsed().as_secs_f64() * self.speed as f64;
         let pause = self.paused;
         self.paused = true;
         self.time_changed = true;
@@ -49,12 +49,12 @@ impl GlobalTimer {
         if !pause { self.pause_changed = true; }
     }
 
-    pub fn get_time(&mut self) -> f32 {
+    pub fn get_time(&mut self) -> f64 {
         if self.paused { return self.midi_time; }
-        return self.midi_time + self.time.elapsed().as_secs_f32() * self.speed;
+        return self.midi_time + self.time.elapsed().as_secs_f64() * self.speed as f64;
     }
 
-    pub fn navigate(&mut self, time: f32) -> () {
+    pub fn navigate(&mut self, time: f64) -> () {
         self.time = Instant::now();
         self.midi_time = time;
         self.time_changed = true;
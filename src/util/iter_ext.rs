@@ -1,180 +1,85 @@
-use crate::midi::midi_track_parser::{MIDIEvent, Note, TempoEvent};
-
-pub fn merge_two_tempo_seqs(seq1: Vec<TempoEvent>, seq2: Vec<TempoEvent>) -> Vec<TempoEvent> {
-    let mut enum1 = seq1.into_iter();
-    let mut enum2 = seq2.into_iter();
-    let mut e1 = enum1.next();
-    let mut e2 = enum2.next();
-    let mut res = Vec::new();
-
-    loop {
-        match e1 {
-            Some(ref en1) => {
-                match e2 {
-                    Some(ref en2) => {
-                        if en1.time < en2.time {
-                            res.push(e1.unwrap());
-                            e1 = enum1.next();
-                        } else {
-                            res.push(e2.unwrap());
-                            e2 = enum2.next();
-                        }
-                    }
-                    None => {
-                        res.push(e1.unwrap());
-                        e1 = enum1.next();
-                    }
-                }
-            },
-            None => {
-                if e2.is_none() { break; }
-                else {
-                    res.push(e2.unwrap());
-                    e2 = enum2.next();
-                }
-            }
-        }
-    }
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 
-    res
+use crate::midi::midi_track_parser::{MIDIEvent, MetaEvent, Note, TempoEvent};
+
+// what merge_sequences orders by. seq is the index of the sequence the element came from,
+// so each type decides which sequence goes first when the times are equal
+pub trait MergeKey {
+    type Key: Ord;
+    fn merge_key(&self, seq: usize) -> Self::Key;
 }
 
-pub fn merge_tempo_evs(seq: Vec<Vec<TempoEvent>>) -> Vec<TempoEvent> {
-    let mut b1 = seq.into_iter().collect::<Vec<_>>();
-    let mut b2 = Vec::new();
-    if b1.len() == 0 {
-        return Vec::new();
+impl MergeKey for Note {
+    type Key = (u64, usize, usize);
+
+    // equal starts go in track order
+    fn merge_key(&self, seq: usize) -> Self::Key {
+        (self.start, self.track(), seq)
     }
-    while b1.len() > 1 {
-        while b1.len() > 0 {
-            if b1.len() == 1 {
-                b2.push(b1.remove(0));
-            } else {
-                b2.push(merge_two_tempo_seqs(b1.remove(0), b1.remove(0)));
-            }
-        }
-        b1 = b2;
-        b2 = Vec::new();
+}
+
+impl MergeKey for MIDIEvent {
+    type Key = (u64, usize);
+
+    // times are never negative, so the bits sort the same as the floats.
+    // equal times keep the order of the sequences
+    fn merge_key(&self, seq: usize) -> Self::Key {
+        (self.time.to_bits(), seq)
     }
-    b1.remove(0)
 }
 
-pub fn merge_two_note_seqs(seq1: Vec<Note>, seq2: Vec<Note>) -> Vec<Note> {
-    let mut enum1 = seq1.into_iter();
-    let mut enum2 = seq2.into_iter();
-    let mut e1 = enum1.next();
-    let mut e2 = enum2.next();
-    let mut res = Vec::new();
-
-    loop {
-        match e1 {
-            Some(ref en1) => {
-                match e2 {
-                    Some(ref en2) => {
-                        if en1.start < en2.start || (en1.start == en2.start && en1.track < en2.track) {
-                            res.push(e1.unwrap());
-                            e1 = enum1.next();
-                        } else {
-                            res.push(e2.unwrap());
-                            e2 = enum2.next();
-                        }
-                    }
-                    None => {
-                        res.push(e1.unwrap());
-                        e1 = enum1.next();
-                    }
-                }
-            },
-            None => {
-                if e2 == None { break; }
-                else {
-                    res.push(e2.unwrap());
-                    e2 = enum2.next();
-                }
-            }
-        }
+impl MergeKey for MetaEvent {
+    type Key = (u64, usize);
+
+    // same as MIDIEvent
+    fn merge_key(&self, seq: usize) -> Self::Key {
+        (self.time.to_bits(), seq)
     }
+}
 
-    res
+impl MergeKey for TempoEvent {
+    type Key = (u64, Reverse<usize>);
+
+    // tempo changes on the same tick go last sequence first, so the first track's tempo
+    // is the one that ends up in effect
+    fn merge_key(&self, seq: usize) -> Self::Key {
+        (self.time, Reverse(seq))
+    }
 }
 
-pub fn merge_notes(seq: Vec<Vec<Note>>) -> Vec<Note> {
-    let mut b1 = seq.into_iter().collect::<Vec<_>>();
-    let mut b2 = Vec::new();
-    if b1.len() == 0 {
-        return Vec::new();
+// k-way merge of already sorted sequences. every element is moved exactly once
+pub fn merge_sequences<T: MergeKey>(seqs: Vec<Vec<T>>) -> Vec<T> {
+    let mut seqs: Vec<Vec<T>> = seqs.into_iter().filter(|s| !s.is_empty()).collect();
+    if seqs.len() <= 1 {
+        return seqs.pop().unwrap_or_default();
     }
-    while b1.len() > 1 {
-        while b1.len() > 0 {
-            if b1.len() == 1 {
-                b2.push(b1.remove(0));
-            } else {
-                b2.push(merge_two_note_seqs(b1.remove(0), b1.remove(0)));
-            }
+
+    let mut res = Vec::with_capacity(seqs.iter().map(|s| s.len()).sum());
+    let mut iters: Vec<std::vec::IntoIter<T>> = seqs.into_iter().map(|s| s.into_iter()).collect();
+    let mut heads: Vec<Option<T>> = iters.iter_mut().map(|it| it.next()).collect();
+
+    let mut heap = BinaryHeap::with_capacity(heads.len());
+    for (seq, head) in heads.iter().enumerate() {
+        if let Some(h) = head {
+            heap.push(Reverse((h.merge_key(seq), seq)));
         }
-        b1 = b2;
-        b2 = Vec::new();
     }
-    b1.remove(0)
-}
 
-pub fn merge_two_seqs(seq1: Vec<MIDIEvent>, seq2: Vec<MIDIEvent>) -> Vec<MIDIEvent> {
-    let mut enum1= seq1.into_iter();
-    let mut enum2 = seq2.into_iter();
-    let mut e1 = enum1.next();
-    let mut e2 = enum2.next();
-    let mut res = Vec::new();
-
-    loop {
-        match e1 {
-            Some(ref mut en1) => {
-                match e2 {
-                    Some(ref mut en2) => {
-                        if en1.time <= en2.time {
-                            //en2.time -= en1.time;
-                            res.push(e1.unwrap());
-                            e1 = enum1.next();
-                        } else {
-                            //en1.time -= en2.time;
-                            res.push(e2.unwrap());
-                            e2 = enum2.next();
-                        }
-                    }
-                    None => {
-                        res.push(e1.unwrap());
-                        e1 = enum1.next();
-                    }
-                }
-            }
-            None => {
-                if e2.is_none() { break; }
-                else {
-                    res.push(e2.unwrap());
-                    e2 = enum2.next();
-                }
-            }
+    while let Some(Reverse((_, seq))) = heap.pop() {
+        res.push(heads[seq].take().unwrap());
+
+        // the last sequence standing can just be copied over
+        if heap.is_empty() {
+            res.extend(iters[seq].by_ref());
+            break;
+        }
+
+        if let Some(next) = iters[seq].next() {
+            heap.push(Reverse((next.merge_key(seq), seq)));
+            heads[seq] = Some(next);
         }
     }
 
     res
 }
-
-pub fn merge_midi_events(seq: Vec<Vec<MIDIEvent>>) -> Vec<MIDIEvent> {
-    let mut b1 = seq.into_iter().collect::<Vec<_>>();
-    let mut b2 = Vec::new();
-    if b1.len() == 0 {
-        return Vec::new();
-    }
-    while b1.len() > 1 {
-        while b1.len() > 0 {
-            if b1.len() == 1 {
-                b2.push(b1.remove(0));
-            } else {
-                b2.push(merge_two_seqs(b1.remove(0), b1.remove(0)));
-            }
-        }
-        b1 = b2;
-        b2 = Vec::new();
-    }
-    b1.remove(0)
-}
\ No newline at end of file